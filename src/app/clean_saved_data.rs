@@ -0,0 +1,186 @@
+use eyre::{bail, Error};
+use std::collections::BTreeSet;
+
+use crate::{
+    config::Config,
+    data::{Compound, Coord, World},
+};
+
+/// Where a saved data file keeps its positioned entries, relative to its `data` compound
+#[derive(Copy, Clone, Debug)]
+enum Entries {
+    /// A list of compounds with a block position in `CX` and `CZ`
+    BlockList(&'static str),
+    /// A compound of compounds with a chunk position in `ChunkX` and `ChunkZ`
+    ChunkCompound(&'static str),
+    /// A long array of chunk positions, packed with x in the low and z in the high bits
+    PackedChunks(&'static str),
+}
+
+/// Saved data files known to reference positions in the world
+///
+/// The legacy structure files are from before 1.13, minecraft reads them once when upgrading
+/// old chunks so stale entries there would leak old structures into new terrain.
+const SAVED_DATA: &[(&str, Entries)] = &[
+    ("raids", Entries::BlockList("Raids")),
+    ("raids_end", Entries::BlockList("Raids")),
+    ("villages", Entries::BlockList("Villages")),
+    ("villages_nether", Entries::BlockList("Villages")),
+    ("villages_end", Entries::BlockList("Villages")),
+    ("chunks", Entries::PackedChunks("Forced")),
+    ("EndCity", Entries::ChunkCompound("Features")),
+    ("Fortress", Entries::ChunkCompound("Features")),
+    ("Mansion", Entries::ChunkCompound("Features")),
+    ("Mineshaft", Entries::ChunkCompound("Features")),
+    ("Monument", Entries::ChunkCompound("Features")),
+    ("Stronghold", Entries::ChunkCompound("Features")),
+    ("Temple", Entries::ChunkCompound("Features")),
+    ("Village", Entries::ChunkCompound("Features")),
+];
+
+#[culpa::throws]
+fn int(entry: &Compound, key: &str) -> i64 {
+    let Some(&fastnbt::Value::Int(value)) = entry.get(key) else {
+        bail!("bad {key}")
+    };
+    i64::from(value)
+}
+
+#[culpa::throws]
+fn clean_block_list(data: &mut Compound, key: &str, kept_chunks: &BTreeSet<Coord<i64>>) -> usize {
+    let Some(entries) = data.get_mut(key) else {
+        return 0;
+    };
+    let fastnbt::Value::List(entries) = entries else {
+        bail!("bad {key}")
+    };
+
+    let mut kept = Vec::new();
+    let mut removed_count = 0;
+    for entry in std::mem::take(entries) {
+        let fastnbt::Value::Compound(compound) = &entry else {
+            bail!("bad {key} entry")
+        };
+        let block = Coord {
+            x: int(compound, "CX")?,
+            z: int(compound, "CZ")?,
+        };
+        if kept_chunks.contains(&block.block_to_chunk()) {
+            kept.push(entry);
+        } else {
+            let _guard = tracing::info_span!("entry", entry.block = %block).entered();
+            tracing::debug!("Removed entry in deleted chunk");
+            removed_count += 1;
+        }
+    }
+    *entries = kept;
+
+    removed_count
+}
+
+#[culpa::throws]
+fn clean_chunk_compound(
+    data: &mut Compound,
+    key: &str,
+    kept_chunks: &BTreeSet<Coord<i64>>,
+) -> usize {
+    let Some(entries) = data.get_mut(key) else {
+        return 0;
+    };
+    let fastnbt::Value::Compound(entries) = entries else {
+        bail!("bad {key}")
+    };
+
+    let mut stale = Vec::new();
+    for (name, entry) in entries.iter() {
+        let fastnbt::Value::Compound(entry) = entry else {
+            bail!("bad {key} entry")
+        };
+        let chunk = Coord {
+            x: int(entry, "ChunkX")?,
+            z: int(entry, "ChunkZ")?,
+        };
+        if !kept_chunks.contains(&chunk) {
+            stale.push((name.clone(), chunk));
+        }
+    }
+
+    for (name, chunk) in &stale {
+        let _guard = tracing::info_span!("entry", entry.chunk = %chunk).entered();
+        entries.remove(name);
+        tracing::debug!("Removed entry in deleted chunk");
+    }
+
+    stale.len()
+}
+
+#[culpa::throws]
+fn clean_packed_chunks(
+    data: &mut Compound,
+    key: &str,
+    kept_chunks: &BTreeSet<Coord<i64>>,
+) -> usize {
+    let Some(entries) = data.get_mut(key) else {
+        return 0;
+    };
+    let fastnbt::Value::LongArray(entries) = entries else {
+        bail!("bad {key}")
+    };
+
+    let mut kept = Vec::new();
+    let mut removed_count = 0;
+    for &packed in entries.iter() {
+        let [x0, x1, x2, x3, z0, z1, z2, z3] = packed.to_le_bytes();
+        let chunk = Coord {
+            x: i64::from(i32::from_le_bytes([x0, x1, x2, x3])),
+            z: i64::from(i32::from_le_bytes([z0, z1, z2, z3])),
+        };
+        if kept_chunks.contains(&chunk) {
+            kept.push(packed);
+        } else {
+            let _guard = tracing::info_span!("entry", entry.chunk = %chunk).entered();
+            tracing::debug!("Removed entry in deleted chunk");
+            removed_count += 1;
+        }
+    }
+    *entries = fastnbt::LongArray::new(kept);
+
+    removed_count
+}
+
+#[culpa::throws]
+#[tracing::instrument(name = "clean_saved_data", skip_all)]
+pub(super) fn run(world: &World, config: &Config) {
+    for (dimension_kind, dimension_config) in &config.dimension {
+        let _guard = tracing::info_span!("dimension", dimension.kind = %dimension_kind).entered();
+
+        let dimension = world.dimension(*dimension_kind);
+        let kept_chunks = dimension_config.kept_chunks();
+
+        let mut removed_count = 0;
+        for &(name, entries) in SAVED_DATA {
+            let Some(mut saved_data) = dimension.saved_data(name)? else {
+                continue;
+            };
+            let _guard = tracing::info_span!("saved_data", saved_data.name = name).entered();
+
+            let Some(fastnbt::Value::Compound(data)) = saved_data.get_mut("data") else {
+                bail!("bad data")
+            };
+            let removed = match entries {
+                Entries::BlockList(key) => clean_block_list(data, key, &kept_chunks)?,
+                Entries::ChunkCompound(key) => clean_chunk_compound(data, key, &kept_chunks)?,
+                Entries::PackedChunks(key) => clean_packed_chunks(data, key, &kept_chunks)?,
+            };
+            if removed == 0 {
+                continue;
+            }
+
+            dimension.save_saved_data(name, &saved_data)?;
+            tracing::info!("Removed {removed} entries in deleted chunks");
+            removed_count += removed;
+        }
+
+        tracing::info!("Removed {removed_count} saved data entries");
+    }
+}
@@ -0,0 +1,27 @@
+use eyre::Error;
+
+use crate::data::{dimension, World};
+
+#[culpa::throws]
+#[tracing::instrument(name = "compact", skip_all)]
+pub(super) fn run(world: &World) {
+    for dimension_kind in dimension::Kind::ALL {
+        let _guard = tracing::info_span!("dimension", dimension.kind = %dimension_kind).entered();
+
+        let dimension = world.dimension(dimension_kind);
+
+        let mut compacted_count = 0;
+        let mut reclaimed_bytes = 0;
+        for region in dimension.regions()?.chain(dimension.entity_regions()?) {
+            let mut region = region?;
+            let _guard = tracing::info_span!("region", region.path = %region.path).entered();
+            let reclaimed = region.compact()?;
+            if reclaimed > 0 {
+                compacted_count += 1;
+                reclaimed_bytes += reclaimed;
+            }
+        }
+
+        tracing::info!("Compacted {compacted_count} regions, reclaiming {reclaimed_bytes} bytes");
+    }
+}
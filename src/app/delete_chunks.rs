@@ -1,105 +1,328 @@
-use eyre::Error;
+use camino::Utf8PathBuf;
+use eyre::{ContextCompat, Error, Result};
+use indicatif::MultiProgress;
 use std::collections::BTreeSet;
 
+use super::{
+    failures::Failures,
+    jobs::{self, FirstError},
+};
 use crate::{
-    config::{self, Config, PersistentArea},
-    data::{Coord, World},
+    config::Config,
+    data::{Coord, Region, World},
+    progress::Progress,
+    report::Report,
 };
 
+/// What happened to the stale chunks of one kept region
+#[derive(Debug)]
+struct Cleaned {
+    path: Utf8PathBuf,
+    /// How many chunks are inside persistent areas
+    kept: usize,
+    deleted: Vec<Coord<i64>>,
+    failed: Vec<(Coord<i64>, Error)>,
+    reclaimed: Result<u64>,
+}
+
+impl Cleaned {
+    fn has_failures(&self) -> bool {
+        !self.failed.is_empty() || self.reclaimed.is_err()
+    }
+}
+
+/// Deletes a kept region's chunks outside persistent areas and compacts it, leaving the logging
+/// to the caller so it can run on any thread
+///
+/// The region is closed rather than returned, so open files don't pile up across thousands of
+/// regions. When errors abort the run it stops at the first chunk that fails, keeping what was
+/// already removed but not compacting.
+#[culpa::throws]
+fn clean(mut region: Region, kept_chunks: &BTreeSet<Coord<i64>>, aborts: bool) -> Cleaned {
+    let all_chunks = Result::<BTreeSet<_>, _>::from_iter(region.chunks())?;
+    let kept = all_chunks.intersection(kept_chunks).count();
+    let mut deleted = Vec::new();
+    let mut failed = Vec::new();
+    for chunk_coord in &all_chunks - kept_chunks {
+        match region.remove_chunk(chunk_coord) {
+            Ok(()) => deleted.push(chunk_coord),
+            Err(error) => {
+                failed.push((chunk_coord, error));
+                if aborts {
+                    break;
+                }
+            }
+        }
+    }
+    let reclaimed = if deleted.is_empty() && failed.is_empty() {
+        Ok(0)
+    } else if aborts && !failed.is_empty() {
+        region.flush().map(|()| 0)
+    } else {
+        region.compact()
+    };
+    Cleaned {
+        path: region.path.clone(),
+        kept,
+        deleted,
+        failed,
+        reclaimed,
+    }
+}
+
+fn deleted_count(result: &Result<Option<Cleaned>>) -> usize {
+    match result {
+        Ok(Some(cleaned)) => cleaned.deleted.len(),
+        Ok(None) | Err(_) => 0,
+    }
+}
+
+/// Applies the error policy to the chunks a kept region failed to delete
+#[culpa::throws]
+fn chunk_failures(
+    failures: &mut Failures,
+    region: Result<Option<Region>>,
+    failed: Vec<(Coord<i64>, Error)>,
+    chunk_span: impl Fn(Coord<i64>) -> tracing::Span,
+) {
+    let mut region = region?.context("region disappeared while deleting chunks")?;
+    for (chunk_coord, error) in failed {
+        let _guard = chunk_span(chunk_coord).entered();
+        failures.chunk(&mut region, chunk_coord, error)?;
+    }
+    region.flush()?;
+}
+
+fn has_failures(result: &Result<Option<Cleaned>>) -> bool {
+    match result {
+        Ok(Some(cleaned)) => cleaned.has_failures(),
+        Ok(None) => false,
+        Err(_) => true,
+    }
+}
+
 #[culpa::throws]
 #[tracing::instrument(name = "delete", skip_all)]
-pub(super) fn run(world: &World, config: &Config) {
-    for (dimension_kind, config::Dimension { persistent }) in &config.dimension {
+pub(super) fn run(
+    world: &World,
+    config: &Config,
+    failures: &mut Failures,
+    bars: &MultiProgress,
+    report: &mut Report,
+) {
+    for (dimension_kind, dimension_config) in &config.dimension {
         let _guard = tracing::info_span!("dimension", dimension.kind = %dimension_kind).entered();
 
         let dimension = world.dimension(*dimension_kind);
+        let dimension_report = report.dimension(*dimension_kind);
 
-        let kept_regions = BTreeSet::from_iter(persistent.iter().flat_map(
-            |PersistentArea::Square {
-                 top_left: tl,
-                 bottom_right: br,
-                 ..
-             }| {
-                let tlr = tl.chunk_to_region();
-                let brr = br.chunk_to_region();
-                ((tlr.x)..=(brr.x))
-                    .flat_map(move |x| ((tlr.z)..=(brr.z)).map(move |z| Coord { x, z }))
-            },
-        ));
-
-        let kept_chunks = BTreeSet::from_iter(persistent.iter().flat_map(
-            |PersistentArea::Square {
-                 top_left: tl,
-                 bottom_right: br,
-                 ..
-             }| {
-                ((tl.x)..=(br.x)).flat_map(move |x| ((tl.z)..=(br.z)).map(move |z| Coord { x, z }))
-            },
-        ));
+        let kept_regions = dimension_config.kept_regions();
+        let kept_chunks = dimension_config.kept_chunks();
+        let aborts = failures.aborts();
+        // when a job fails, regions other jobs deleted after it are still logged and reported
+        // before the error is returned
+        let mut first_error = FirstError::default();
 
-        let all_regions = Result::<BTreeSet<_>, _>::from_iter(
-            dimension.regions()?.map(|r| Ok::<_, Error>(r?.coord)),
-        )?;
+        // listed by name so a region too damaged to open is still deleted
+        let all_regions = Result::<BTreeSet<_>, _>::from_iter(dimension.region_coords()?)?;
+        let stale_regions = &all_regions - &kept_regions;
 
+        let external_files = dimension.external_chunk_files()?;
+        let progress = Progress::new(
+            bars,
+            "delete",
+            *dimension_kind,
+            stale_regions.len() + kept_regions.len(),
+        );
+        let removed = jobs::map_ordered(
+            Vec::from_iter(stale_regions),
+            aborts,
+            |coord| {
+                let result = dimension.remove_region(coord, &external_files);
+                progress.region_done(0);
+                (coord, result)
+            },
+            |(_, result)| result.is_err(),
+        );
         let mut deleted_region_count = 0;
-        for coord in &all_regions - &kept_regions {
+        for (coord, result) in removed.into_iter().flatten() {
             let _guard = tracing::info_span!("region", region.coord = %coord).entered();
-            dimension.remove_region(coord)?;
+            let freed = match result {
+                Ok(freed) => freed,
+                Err(error) => {
+                    first_error.keep(failures.region(&dimension.region_path(coord), error));
+                    continue;
+                }
+            };
             tracing::debug!("Deleted region");
             deleted_region_count += 1;
+            dimension_report.deleted_regions.push(coord);
+            dimension_report.reclaimed_bytes += freed;
         }
+        first_error.finish()?;
 
+        let cleaned = jobs::map_ordered(
+            Vec::from_iter(kept_regions.iter().copied()),
+            aborts,
+            |coord| {
+                let result = dimension.region(coord).and_then(|region| {
+                    region
+                        .map(|region| clean(region, &kept_chunks, aborts))
+                        .transpose()
+                });
+                progress.region_done(deleted_count(&result));
+                (coord, result)
+            },
+            |(_, result)| has_failures(result),
+        );
         let mut deleted_chunk_count = 0;
-        for &region_coord in &kept_regions {
+        let mut reclaimed_bytes = 0;
+        for (region_coord, result) in cleaned.into_iter().flatten() {
             let _guard = tracing::info_span!("in_region", region.coord = %region_coord).entered();
-            if let Some(mut region) = dimension.region(region_coord)? {
-                let all_chunks = Result::<BTreeSet<_>, _>::from_iter(region.chunks())?;
-                for chunk_coord in &all_chunks - &kept_chunks {
-                    let _guard =
-                        tracing::info_span!("chunk", chunk.absolute_coord = %chunk_coord).entered();
-                    region.remove_chunk(chunk_coord)?;
-                    tracing::debug!("Deleted chunk");
-                    deleted_chunk_count += 1;
+            let cleaned = match result {
+                Ok(Some(cleaned)) => cleaned,
+                Ok(None) => continue,
+                Err(error) => {
+                    first_error
+                        .keep(failures.kept_region(&dimension.region_path(region_coord), error));
+                    continue;
+                }
+            };
+            dimension_report.kept_regions += 1;
+            dimension_report.kept_chunks += cleaned.kept;
+            for chunk_coord in cleaned.deleted {
+                let _guard =
+                    tracing::info_span!("chunk", chunk.absolute_coord = %chunk_coord).entered();
+                tracing::debug!("Deleted chunk");
+                deleted_chunk_count += 1;
+                dimension_report.deleted_chunks.push(chunk_coord);
+            }
+            if !cleaned.failed.is_empty() {
+                first_error.keep(chunk_failures(
+                    failures,
+                    dimension.region(region_coord),
+                    cleaned.failed,
+                    |chunk_coord| tracing::info_span!("chunk", chunk.absolute_coord = %chunk_coord),
+                ));
+            }
+            match cleaned.reclaimed {
+                Ok(reclaimed) => {
+                    reclaimed_bytes += reclaimed;
+                    dimension_report.reclaimed_bytes += reclaimed;
+                }
+                Err(error) => {
+                    first_error.keep(failures.other(&format!("compacting {}", cleaned.path), error))
                 }
             }
         }
 
-        tracing::info!("Deleted {deleted_region_count} regions and {deleted_chunk_count} chunks");
+        drop(progress);
+        tracing::info!("Deleted {deleted_region_count} regions and {deleted_chunk_count} chunks, compacting reclaimed {reclaimed_bytes} bytes");
+        first_error.finish()?;
 
         if config.entities.cull {
             let _guard = tracing::info_span!("entities").entered();
-            let all_entity_regions = Result::<BTreeSet<_>, _>::from_iter(
-                dimension
-                    .entity_regions()?
-                    .map(|r| Ok::<_, Error>(r?.coord)),
-            )?;
+            let all_entity_regions =
+                Result::<BTreeSet<_>, _>::from_iter(dimension.entity_region_coords()?)?;
+
+            let stale_entity_regions = &all_entity_regions - &kept_regions;
+            let external_files = dimension.entity_external_chunk_files()?;
 
+            let progress = Progress::new(
+                bars,
+                "cull",
+                *dimension_kind,
+                stale_entity_regions.len() + kept_regions.len(),
+            );
+            let removed = jobs::map_ordered(
+                Vec::from_iter(stale_entity_regions),
+                aborts,
+                |coord| {
+                    let result = dimension.remove_entity_region(coord, &external_files);
+                    progress.region_done(0);
+                    (coord, result)
+                },
+                |(_, result)| result.is_err(),
+            );
             let mut deleted_entity_region_count = 0;
-            for coord in &all_entity_regions - &kept_regions {
+            for (coord, result) in removed.into_iter().flatten() {
                 let _guard = tracing::info_span!("region", region.coord = %coord).entered();
-                dimension.remove_entity_region(coord)?;
+                let freed = match result {
+                    Ok(freed) => freed,
+                    Err(error) => {
+                        first_error
+                            .keep(failures.region(&dimension.entity_region_path(coord), error));
+                        continue;
+                    }
+                };
                 tracing::debug!("Deleted entity region");
                 deleted_entity_region_count += 1;
+                dimension_report.deleted_entity_regions.push(coord);
+                dimension_report.reclaimed_bytes += freed;
             }
+            first_error.finish()?;
 
+            let cleaned = jobs::map_ordered(
+                Vec::from_iter(kept_regions.iter().copied()),
+                aborts,
+                |coord| {
+                    let result = dimension.entity_region(coord).and_then(|region| {
+                        region
+                            .map(|region| clean(region, &kept_chunks, aborts))
+                            .transpose()
+                    });
+                    progress.region_done(deleted_count(&result));
+                    (coord, result)
+                },
+                |(_, result)| has_failures(result),
+            );
             let mut deleted_entity_chunk_count = 0;
-            for &region_coord in &kept_regions {
+            let mut reclaimed_entity_bytes = 0;
+            for (region_coord, result) in cleaned.into_iter().flatten() {
                 let _guard =
                     tracing::info_span!("in_region", region.coord = %region_coord).entered();
-                if let Some(mut region) = dimension.entity_region(region_coord)? {
-                    let all_chunks = Result::<BTreeSet<_>, _>::from_iter(region.chunks())?;
-                    for chunk_coord in &all_chunks - &kept_chunks {
-                        let _guard =
+                let cleaned = match result {
+                    Ok(Some(cleaned)) => cleaned,
+                    Ok(None) => continue,
+                    Err(error) => {
+                        first_error.keep(
+                            failures
+                                .kept_region(&dimension.entity_region_path(region_coord), error),
+                        );
+                        continue;
+                    }
+                };
+                for chunk_coord in cleaned.deleted {
+                    let _guard =
+                        tracing::info_span!("chunk", entity_chunk.absolute_coord = %chunk_coord)
+                            .entered();
+                    tracing::debug!("Deleted entity chunk");
+                    deleted_entity_chunk_count += 1;
+                    dimension_report.deleted_entity_chunks.push(chunk_coord);
+                }
+                if !cleaned.failed.is_empty() {
+                    first_error.keep(chunk_failures(
+                        failures,
+                        dimension.entity_region(region_coord),
+                        cleaned.failed,
+                        |chunk_coord| {
                             tracing::info_span!("chunk", entity_chunk.absolute_coord = %chunk_coord)
-                                .entered();
-                        region.remove_chunk(chunk_coord)?;
-                        tracing::debug!("Deleted entity chunk");
-                        deleted_entity_chunk_count += 1;
+                        },
+                    ));
+                }
+                match cleaned.reclaimed {
+                    Ok(reclaimed) => {
+                        reclaimed_entity_bytes += reclaimed;
+                        dimension_report.reclaimed_bytes += reclaimed;
                     }
+                    Err(error) => first_error
+                        .keep(failures.other(&format!("compacting {}", cleaned.path), error)),
                 }
             }
 
-            tracing::info!("Deleted {deleted_entity_region_count} entity regions and {deleted_entity_chunk_count} entity chunks");
+            drop(progress);
+            tracing::info!("Deleted {deleted_entity_region_count} entity regions and {deleted_entity_chunk_count} entity chunks, compacting reclaimed {reclaimed_entity_bytes} bytes");
+            first_error.finish()?;
         }
     }
 }
@@ -0,0 +1,111 @@
+use eyre::{bail, Error};
+use std::collections::HashSet;
+
+use crate::{
+    config::Config,
+    data::{dimension, Coord, World},
+};
+
+/// How many end gateways the dragon fight spawns over successive kills
+const GATEWAY_COUNT: i32 = 20;
+
+/// The position of an end gateway, calculated the same way Minecraft does
+fn gateway(index: i32) -> Coord<i64> {
+    let angle = 2.0 * (-std::f64::consts::PI + std::f64::consts::PI / 20.0 * f64::from(index));
+    #[allow(clippy::as_conversions)] // no alternative yet
+    Coord {
+        x: (96.0 * angle.cos()).floor() as i64,
+        z: (96.0 * angle.sin()).floor() as i64,
+    }
+}
+
+#[culpa::throws]
+#[tracing::instrument(name = "dragon_fight", skip_all)]
+pub(super) fn run(world: &World, config: &Config) {
+    let Some(end) = config.dimension.get(&dimension::Kind::End) else {
+        return;
+    };
+    let is_kept = |block: Coord<i64>| {
+        end.persistent
+            .iter()
+            .any(|area| area.contains(block.block_to_chunk()))
+    };
+
+    let mut level = world.level()?;
+    let Some(fastnbt::Value::Compound(data)) = level.get_mut("Data") else {
+        bail!("bad Data")
+    };
+    let Some(dragon_fight) = data.get_mut("DragonFight") else {
+        tracing::info!("No dragon fight has happened yet");
+        return;
+    };
+    let fastnbt::Value::Compound(dragon_fight) = dragon_fight else {
+        bail!("bad DragonFight")
+    };
+
+    let portal = match dragon_fight.get("ExitPortalLocation") {
+        None => Coord { x: 0, z: 0 },
+        Some(fastnbt::Value::IntArray(position)) => {
+            let [x, _, z] = position[..] else {
+                bail!("bad ExitPortalLocation")
+            };
+            Coord {
+                x: i64::from(x),
+                z: i64::from(z),
+            }
+        }
+        Some(fastnbt::Value::Compound(position)) => {
+            let (Some(&fastnbt::Value::Int(x)), Some(&fastnbt::Value::Int(z))) =
+                (position.get("X"), position.get("Z"))
+            else {
+                bail!("bad ExitPortalLocation")
+            };
+            Coord {
+                x: i64::from(x),
+                z: i64::from(z),
+            }
+        }
+        Some(_) => bail!("bad ExitPortalLocation"),
+    };
+    let _guard = tracing::info_span!("portal", portal.coord = %portal).entered();
+
+    // the exit portal extends 3 blocks out from its center
+    let portal_kept = [(-3, -3), (3, -3), (-3, 3), (3, 3)]
+        .into_iter()
+        .all(|(x, z)| {
+            is_kept(Coord {
+                x: portal.x + x,
+                z: portal.z + z,
+            })
+        });
+
+    if portal_kept {
+        let Some(fastnbt::Value::List(gateways)) = dragon_fight.get_mut("Gateways") else {
+            bail!("bad Gateways")
+        };
+        let pending = HashSet::<i64>::from_iter(gateways.iter().filter_map(fastnbt::Value::as_i64));
+        let mut restored_count = 0;
+        for index in 0..GATEWAY_COUNT {
+            if pending.contains(&i64::from(index)) || is_kept(gateway(index)) {
+                continue;
+            }
+            let _guard =
+                tracing::info_span!("gateway", gateway.index = index, gateway.coord = %gateway(index))
+                    .entered();
+            gateways.push(fastnbt::Value::Int(index));
+            tracing::debug!("Gateway was regenerated, will respawn on next kill");
+            restored_count += 1;
+        }
+        if restored_count == 0 {
+            tracing::info!("Exit portal and all spawned gateways were kept");
+            return;
+        }
+        tracing::info!("Exit portal was kept, restored {restored_count} regenerated gateways");
+    } else {
+        // with no saved state minecraft will start a fresh fight, including a new exit portal
+        data.remove("DragonFight");
+        tracing::info!("Exit portal was regenerated, reset dragon fight");
+    }
+
+    world.save_level(&level)?;
+}
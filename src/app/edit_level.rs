@@ -0,0 +1,152 @@
+use eyre::{bail, ContextCompat, Error};
+
+use crate::{
+    config::Config,
+    data::{dimension, Coord, World},
+};
+
+#[culpa::throws]
+#[tracing::instrument(name = "level", skip_all)]
+pub(super) fn run(world: &World, config: &Config) {
+    let Some(level_config) = &config.level else {
+        return;
+    };
+
+    let mut level = world.level()?;
+    let Some(fastnbt::Value::Compound(data)) = level.get_mut("Data") else {
+        bail!("bad Data")
+    };
+
+    if !level_config.game_rules.is_empty() {
+        let Some(fastnbt::Value::Compound(game_rules)) = data.get_mut("GameRules") else {
+            bail!("bad GameRules")
+        };
+        for (name, value) in &level_config.game_rules {
+            let _guard =
+                tracing::info_span!("game_rule", game_rule.name = %name, game_rule.value = %value)
+                    .entered();
+            let old = game_rules.insert(name.clone(), fastnbt::Value::String(value.to_string()));
+            if old.is_none() {
+                tracing::warn!("Game rule was not previously set, check the name is correct");
+            }
+            tracing::info!("Set game rule");
+        }
+    }
+
+    if level_config.reset_time {
+        data.insert("DayTime".into(), fastnbt::Value::Long(0));
+        data.insert("Time".into(), fastnbt::Value::Long(0));
+        tracing::info!("Reset time");
+    }
+
+    if level_config.clear_weather {
+        data.insert("raining".into(), fastnbt::Value::Byte(0));
+        data.insert("rainTime".into(), fastnbt::Value::Int(0));
+        data.insert("thundering".into(), fastnbt::Value::Byte(0));
+        data.insert("thunderTime".into(), fastnbt::Value::Int(0));
+        tracing::info!("Cleared weather");
+    }
+
+    let persistent = config
+        .dimension
+        .get(&dimension::Kind::Overworld)
+        .map(|dimension| &dimension.persistent);
+    let is_persistent = |coord: Coord<i64>| {
+        persistent.map_or(true, |persistent| {
+            persistent
+                .iter()
+                .any(|area| area.contains(coord.block_to_chunk()))
+        })
+    };
+
+    let new_spawn = if let Some(new_spawn) = level_config.spawn {
+        if !is_persistent(new_spawn.to_block_coord()) {
+            tracing::warn!(
+                "Configured spawn is outside the persistent areas, it will be regenerated"
+            );
+        }
+        Some((new_spawn.to_block_coord(), new_spawn.block_y()))
+    } else if let Some(persistent) = persistent {
+        // the current spawn is only needed to check whether it has to move
+        let (
+            Some(&fastnbt::Value::Int(x)),
+            Some(&fastnbt::Value::Int(y)),
+            Some(&fastnbt::Value::Int(z)),
+        ) = (data.get("SpawnX"), data.get("SpawnY"), data.get("SpawnZ"))
+        else {
+            bail!("bad SpawnX/SpawnY/SpawnZ")
+        };
+        let spawn = Coord {
+            x: i64::from(x),
+            z: i64::from(z),
+        };
+        let _guard = tracing::info_span!("spawn", old.spawn = %format!("{x},{y},{z}")).entered();
+
+        if is_persistent(spawn) {
+            None
+        } else {
+            let chunk = spawn.block_to_chunk();
+            let nearest = persistent.iter().min_by_key(|area| {
+                let center = area.center();
+                (center.x - chunk.x).pow(2) + (center.z - chunk.z).pow(2)
+            });
+            match nearest {
+                Some(area) => {
+                    let center = area.center();
+                    let coord = Coord {
+                        x: center.x * 16 + 8,
+                        z: center.z * 16 + 8,
+                    };
+                    tracing::info!(
+                        "Spawn is outside the persistent areas, moving it to the nearest one"
+                    );
+                    // the old height means nothing at the new position
+                    Some((coord, surface_height(world, coord)?))
+                }
+                None => {
+                    tracing::warn!(
+                        "Spawn is outside the persistent areas, but there are none to move it to"
+                    );
+                    None
+                }
+            }
+        }
+    } else {
+        None
+    };
+
+    if let Some((coord, y)) = new_spawn {
+        let _guard = tracing::info_span!("to", new.spawn = %format!("{},{y},{}", coord.x, coord.z))
+            .entered();
+        data.insert(
+            "SpawnX".into(),
+            fastnbt::Value::Int(i32::try_from(coord.x)?),
+        );
+        data.insert("SpawnY".into(), fastnbt::Value::Int(i32::try_from(y)?));
+        data.insert(
+            "SpawnZ".into(),
+            fastnbt::Value::Int(i32::try_from(coord.z)?),
+        );
+        tracing::info!("Set spawn");
+    }
+
+    world.save_level(&level)?;
+}
+
+/// The height a player stands at on the ground of the given overworld block column
+#[culpa::throws]
+fn surface_height(world: &World, coord: Coord<i64>) -> i64 {
+    let chunk_coord = coord.block_to_chunk();
+    let chunk = match world
+        .dimension(dimension::Kind::Overworld)
+        .region(chunk_coord.chunk_to_region())?
+    {
+        Some(mut region) => region.chunk(chunk_coord)?,
+        None => None,
+    }
+    .context("the new spawn's chunk was never generated, set level.spawn instead")?;
+    let heights = chunk.heightmaps()?.ocean_floor()?;
+    i64::from(
+        heights[usize::try_from(coord.z.rem_euclid(16))?][usize::try_from(coord.x.rem_euclid(16))?],
+    )
+}
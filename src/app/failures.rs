@@ -0,0 +1,147 @@
+use camino::Utf8Path;
+use eyre::{bail, Error};
+
+use crate::data::{Coord, Region};
+
+/// What to do when a stage hits a region or chunk it can't process
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default, clap::ValueEnum)]
+pub(super) enum ErrorPolicy {
+    /// Stop the run at the first error
+    #[default]
+    Abort,
+    /// Leave the region or chunk as it is and carry on, reporting it at the end
+    Skip,
+    /// Move the region or chunk into `fc5-tool-quarantine` and carry on, reporting it at the end,
+    /// ones inside persistent areas are skipped instead so they aren't regenerated
+    Quarantine,
+}
+
+/// Applies the error policy to failures during stages, keeping track of what was skipped
+#[derive(Debug)]
+pub(super) struct Failures {
+    policy: ErrorPolicy,
+    skipped: Vec<String>,
+}
+
+impl Failures {
+    pub(super) fn new(policy: ErrorPolicy) -> Self {
+        Self {
+            policy,
+            skipped: Vec::new(),
+        }
+    }
+
+    /// Whether the first failure ends the run
+    pub(super) fn aborts(&self) -> bool {
+        self.policy == ErrorPolicy::Abort
+    }
+
+    /// Descriptions of everything skipped so far
+    pub(super) fn skipped(&self) -> &[String] {
+        &self.skipped
+    }
+
+    /// Handles a chunk outside persistent areas that couldn't be processed, returning the error if
+    /// the run should abort
+    #[culpa::throws]
+    pub(super) fn chunk(&mut self, region: &mut Region, coord: Coord<i64>, error: Error) {
+        let error = match self.policy {
+            ErrorPolicy::Abort => Err(error)?,
+            ErrorPolicy::Skip => error,
+            ErrorPolicy::Quarantine => match region.quarantine_chunk(coord) {
+                Ok(path) => {
+                    tracing::warn!("Quarantined chunk to {path}: {error:#}");
+                    self.skipped.push(format!(
+                        "chunk {coord} in {}, quarantined to {path}: {error:#}",
+                        region.path
+                    ));
+                    return;
+                }
+                Err(quarantine_error) => {
+                    error.wrap_err(format!("failed to quarantine: {quarantine_error:#}"))
+                }
+            },
+        };
+        tracing::warn!("Skipped chunk: {error:#}");
+        self.skipped
+            .push(format!("chunk {coord} in {}: {error:#}", region.path));
+    }
+
+    /// Handles a chunk inside a persistent area that couldn't be processed, it is only ever
+    /// skipped since quarantining it would have it regenerated
+    #[culpa::throws]
+    pub(super) fn kept_chunk(&mut self, region_path: &Utf8Path, coord: Coord<i64>, error: Error) {
+        match self.policy {
+            ErrorPolicy::Abort => Err(error)?,
+            ErrorPolicy::Skip | ErrorPolicy::Quarantine => {
+                tracing::warn!("Skipped chunk: {error:#}");
+                self.skipped
+                    .push(format!("chunk {coord} in {region_path}: {error:#}"));
+            }
+        }
+    }
+
+    /// Handles a region wholly outside persistent areas that couldn't be processed, returning the
+    /// error if the run should abort
+    #[culpa::throws]
+    pub(super) fn region(&mut self, path: &Utf8Path, error: Error) {
+        let error = match self.policy {
+            ErrorPolicy::Abort => Err(error)?,
+            ErrorPolicy::Skip => error,
+            ErrorPolicy::Quarantine => match Region::quarantine(path) {
+                Ok(new_path) => {
+                    tracing::warn!("Quarantined region to {new_path}: {error:#}");
+                    self.skipped.push(format!(
+                        "region {path}, quarantined to {new_path}: {error:#}"
+                    ));
+                    return;
+                }
+                Err(quarantine_error) => {
+                    error.wrap_err(format!("failed to quarantine: {quarantine_error:#}"))
+                }
+            },
+        };
+        tracing::warn!("Skipped region: {error:#}");
+        self.skipped.push(format!("region {path}: {error:#}"));
+    }
+
+    /// Handles a region overlapping persistent areas that couldn't be processed, it is only ever
+    /// skipped and left in place since quarantining it would have the areas regenerated
+    #[culpa::throws]
+    pub(super) fn kept_region(&mut self, path: &Utf8Path, error: Error) {
+        match self.policy {
+            ErrorPolicy::Abort => Err(error)?,
+            ErrorPolicy::Skip | ErrorPolicy::Quarantine => {
+                tracing::warn!("Skipped region: {error:#}");
+                self.skipped.push(format!("region {path}: {error:#}"));
+            }
+        }
+    }
+
+    /// Handles a failure that isn't tied to a single chunk or region, so can only be skipped
+    #[culpa::throws]
+    pub(super) fn other(&mut self, description: &str, error: Error) {
+        match self.policy {
+            ErrorPolicy::Abort => Err(error)?,
+            ErrorPolicy::Skip | ErrorPolicy::Quarantine => {
+                tracing::warn!("Skipped {description}: {error:#}");
+                self.skipped.push(format!("{description}: {error:#}"));
+            }
+        }
+    }
+
+    /// Lists everything that was skipped, failing the run if anything was
+    #[culpa::throws]
+    pub(super) fn finish(self) {
+        if self.skipped.is_empty() {
+            return;
+        }
+        for skipped in &self.skipped {
+            tracing::error!("Skipped {skipped}");
+        }
+        bail!(
+            "{} regions or chunks were skipped because of errors",
+            self.skipped.len()
+        );
+    }
+}
@@ -1,17 +1,51 @@
 use eyre::Error;
+use indicatif::MultiProgress;
+use std::collections::BTreeMap;
 
+use super::{
+    failures::Failures,
+    jobs::{self, FirstError},
+};
 use crate::{
-    config::{self, Config, PersistentArea},
+    config::{self, Blending, Config, PersistentArea},
     data::{
-        Coord,
+        Coord, Direction,
         Direction::{East, North, South, West},
-        World,
+        Region, World,
     },
+    progress::Progress,
+    report::{BlendedChunk, Report},
 };
 
+/// Forces blending on one border chunk, returning whether the chunk exists
+#[culpa::throws]
+fn force(
+    region: &mut Region,
+    coord: Coord<i64>,
+    directions: [Direction; 2],
+    blending: Blending,
+) -> bool {
+    let Some(mut chunk) = region.chunk(coord)? else {
+        return false;
+    };
+    if let Some(offset) = blending.offset {
+        chunk.force_blending_with_heights(directions, offset)?;
+    } else {
+        chunk.force_blending()?;
+    }
+    region.save_chunk(&chunk)?;
+    true
+}
+
 #[culpa::throws]
 #[tracing::instrument(name = "blend", skip_all)]
-pub(super) fn run(world: &World, config: &Config) {
+pub(super) fn run(
+    world: &World,
+    config: &Config,
+    failures: &mut Failures,
+    bars: &MultiProgress,
+    report: &mut Report,
+) {
     for (dimension_kind, config::Dimension { persistent }) in &config.dimension {
         let _guard = tracing::info_span!("dimension", dimension.kind = %dimension_kind).entered();
 
@@ -58,31 +92,100 @@ pub(super) fn run(world: &World, config: &Config) {
                 .flatten(),
         );
 
+        // blend all of a region's border chunks while it is open, rather than reopening it per
+        // chunk, and so different regions can be blended in parallel
+        let mut coords_by_region = BTreeMap::<_, Vec<_>>::new();
+        for border_chunk @ (coord, _, _) in coords {
+            coords_by_region
+                .entry(coord.chunk_to_region())
+                .or_default()
+                .push(border_chunk);
+        }
+
+        let progress = Progress::new(bars, "blend", *dimension_kind, coords_by_region.len());
+        let blended = jobs::map_ordered(
+            Vec::from_iter(coords_by_region),
+            failures.aborts(),
+            |(region_coord, coords)| {
+                let chunk_count = coords.len();
+                // the region is closed here rather than returned, so open files don't pile up
+                // across thousands of regions
+                let result = dimension.region(region_coord).and_then(|region| {
+                    region
+                        .map(|mut region| {
+                            let forced = Vec::from_iter(coords.into_iter().map(
+                                |(coord, directions, blending)| {
+                                    let result = force(&mut region, coord, directions, blending);
+                                    (coord, directions, blending, result)
+                                },
+                            ));
+                            region.flush()?;
+                            Ok(forced)
+                        })
+                        .transpose()
+                });
+                progress.region_done(chunk_count);
+                (region_coord, chunk_count, result)
+            },
+            |(_, _, result)| match result {
+                Ok(Some(forced)) => forced.iter().any(|(_, _, _, result)| result.is_err()),
+                Ok(None) => false,
+                Err(_) => true,
+            },
+        );
+
         let mut forced_chunk_count = 0;
-        for (coord, directions, blending) in coords {
-            let _guard = tracing::info_span!("chunk", chunk.absolute_coord = %coord).entered();
-            let Some(mut region) = dimension.region_for_chunk(coord)? else {
-                tracing::warn!(
-                    "Missing chunk on persistent border, will result in unblended regeneration"
-                );
-                continue;
+        // when a job fails, chunks other jobs blended after it are still logged and reported
+        // before the error is returned
+        let mut first_error = FirstError::default();
+        for (region_coord, chunk_count, result) in blended.into_iter().flatten() {
+            let _guard = tracing::info_span!("in_region", region.coord = %region_coord).entered();
+            let forced = match result {
+                Ok(Some(forced)) => forced,
+                Ok(None) => {
+                    tracing::warn!(
+                        "Missing {chunk_count} chunks on persistent border, will result in unblended regeneration"
+                    );
+                    continue;
+                }
+                Err(error) => {
+                    first_error
+                        .keep(failures.kept_region(&dimension.region_path(region_coord), error));
+                    continue;
+                }
             };
-            let Some(mut chunk) = region.chunk(coord)? else {
-                tracing::warn!(
-                    "Missing chunk on persistent border, will result in unblended regeneration"
-                );
-                continue;
-            };
-            if let Some(offset) = blending.offset {
-                chunk.force_blending_with_heights(directions, offset)?;
-            } else {
-                chunk.force_blending()?;
+
+            for (coord, directions, blending, result) in forced {
+                let _guard = tracing::info_span!("chunk", chunk.absolute_coord = %coord).entered();
+                match result {
+                    Ok(true) => {
+                        tracing::debug!(directions = ?directions, "Forced blending");
+                        forced_chunk_count += 1;
+                        report
+                            .dimension(*dimension_kind)
+                            .blended_chunks
+                            .push(BlendedChunk {
+                                coord,
+                                directions,
+                                height_offset: blending.offset,
+                            });
+                    }
+                    Ok(false) => tracing::warn!(
+                        "Missing chunk on persistent border, will result in unblended regeneration"
+                    ),
+                    Err(error) => {
+                        first_error.keep(failures.kept_chunk(
+                            &dimension.region_path(region_coord),
+                            coord,
+                            error,
+                        ));
+                    }
+                }
             }
-            region.save_chunk(&chunk)?;
-            tracing::debug!(directions = ?directions, "Forced blending");
-            forced_chunk_count += 1;
         }
 
+        drop(progress);
         tracing::info!("Forced blending on {forced_chunk_count} chunks");
+        first_error.finish()?;
     }
 }
@@ -0,0 +1,66 @@
+use eyre::{bail, Error};
+
+use crate::data::{dimension, Repair, World};
+
+#[culpa::throws]
+#[tracing::instrument(name = "fsck", skip_all)]
+pub(super) fn run(world: &World, repair: bool) {
+    let mut problem_count = 0;
+    let mut repaired_count = 0;
+    for dimension_kind in dimension::Kind::ALL {
+        let _guard = tracing::info_span!("dimension", dimension.kind = %dimension_kind).entered();
+
+        let dimension = world.dimension(dimension_kind);
+
+        let mut region_count = 0;
+        for region in dimension
+            .regions()?
+            .chain(dimension.entity_regions()?)
+            .chain(dimension.poi_regions()?)
+        {
+            let mut region = match region {
+                Ok(region) => region,
+                Err(error) => {
+                    // nothing in a region can be trusted without its header, so leave it alone
+                    tracing::warn!("Unreadable region: {error:#}");
+                    problem_count += 1;
+                    continue;
+                }
+            };
+            let _guard = tracing::info_span!("region", region.path = %region.path).entered();
+            region_count += 1;
+
+            for (chunk_coord, problem) in region.check()? {
+                let _guard =
+                    tracing::info_span!("chunk", chunk.absolute_coord = %chunk_coord).entered();
+                tracing::warn!("Chunk {problem}");
+                problem_count += 1;
+
+                if repair {
+                    match region.repair(chunk_coord, &problem)? {
+                        Repair::Dropped => tracing::info!("Dropped chunk"),
+                        Repair::Relocated(new_coord) => {
+                            tracing::info!(chunk.new_coord = %new_coord, "Relocated chunk");
+                        }
+                        Repair::Unneeded => {
+                            tracing::debug!("Already fixed by an earlier repair");
+                            continue;
+                        }
+                    }
+                    repaired_count += 1;
+                }
+            }
+            region.flush()?;
+        }
+
+        tracing::info!("Checked {region_count} regions");
+    }
+
+    if repair {
+        tracing::info!("Found {problem_count} problems, repaired {repaired_count}");
+    } else if problem_count > 0 {
+        bail!("found {problem_count} problems, run with --repair to fix them");
+    } else {
+        tracing::info!("Found no problems");
+    }
+}
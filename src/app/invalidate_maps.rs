@@ -0,0 +1,63 @@
+use eyre::Error;
+use std::time::SystemTime;
+
+use crate::{
+    config::{Config, StaleMaps},
+    data::{Coord, World},
+};
+
+#[culpa::throws]
+#[tracing::instrument(name = "maps", skip_all)]
+pub(super) fn run(world: &World, config: &Config) {
+    let Some(maps) = &config.maps else {
+        return;
+    };
+
+    let timestamp = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)?
+        .as_secs();
+
+    let mut stale_map_count = 0;
+    for id in world.maps()? {
+        let id = id?;
+        let _guard = tracing::info_span!("map", map.id = id).entered();
+        let mut map = world.map(id)?;
+
+        let Some(dimension_kind) = map.dimension()? else {
+            tracing::debug!("Map is of an unknown dimension");
+            continue;
+        };
+        let Some(dimension) = config.dimension.get(&dimension_kind) else {
+            tracing::debug!("Map is of a disabled dimension");
+            continue;
+        };
+        let _guard = tracing::info_span!("dimension", dimension.kind = %dimension_kind).entered();
+
+        let kept_chunks = dimension.kept_chunks();
+        let is_stale = |chunk: Coord<i64>| !kept_chunks.contains(&chunk);
+        if !map.pixel_chunks()?.into_iter().any(is_stale) {
+            tracing::debug!("Map only shows persistent chunks");
+            continue;
+        }
+        stale_map_count += 1;
+
+        if map.locked()? {
+            tracing::info!("Locked map shows regenerated chunks, leaving it as is");
+            continue;
+        }
+
+        match maps.stale {
+            StaleMaps::Clear => {
+                let cleared = map.clear(is_stale)?;
+                world.save_map(&map)?;
+                tracing::info!("Cleared {cleared} pixels showing regenerated chunks");
+            }
+            StaleMaps::Archive => {
+                world.archive_map(id, timestamp)?;
+                tracing::info!("Archived map showing regenerated chunks");
+            }
+        }
+    }
+
+    tracing::info!("Found {stale_map_count} maps showing regenerated chunks");
+}
@@ -0,0 +1,55 @@
+use eyre::{Error, Result};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// The first error that aborts the run, held back while the results of jobs that were already
+/// running past it are still logged and reported
+#[derive(Debug, Default)]
+pub(super) struct FirstError(Option<Error>);
+
+impl FirstError {
+    pub(super) fn keep(&mut self, result: Result<()>) {
+        if let Err(error) = result {
+            self.0.get_or_insert(error);
+        }
+    }
+
+    /// Returns the error kept so far, once every result before it has been handled
+    pub(super) fn finish(&mut self) -> Result<()> {
+        match self.0.take() {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Does `work` for every item across the `--jobs` threads, returning the results in the order of
+/// the items so they are logged the same way however many jobs there are
+///
+/// When errors abort the run, items that haven't started by the time one fails are left undone and
+/// come back as `None`, ones already running finish and should be reported before returning the
+/// error, see [`FirstError`]. The work runs in the caller's span, which the job threads wouldn't
+/// otherwise be in.
+pub(super) fn map_ordered<T: Send, R: Send>(
+    items: Vec<T>,
+    abort_on_failure: bool,
+    work: impl Fn(T) -> R + Sync + Send,
+    is_failure: impl Fn(&R) -> bool + Sync + Send,
+) -> Vec<Option<R>> {
+    let aborted = AtomicBool::new(false);
+    let span = tracing::Span::current();
+    items
+        .into_par_iter()
+        .map(|item| {
+            let _guard = span.enter();
+            if aborted.load(Ordering::Relaxed) {
+                return None;
+            }
+            let result = work(item);
+            if abort_on_failure && is_failure(&result) {
+                aborted.store(true, Ordering::Relaxed);
+            }
+            Some(result)
+        })
+        .collect()
+}
@@ -0,0 +1,155 @@
+use eyre::{bail, ensure, ContextCompat, Error};
+use itertools::Itertools;
+use std::{
+    collections::{btree_map::Entry, BTreeMap, BTreeSet},
+    io::Write,
+};
+
+use crate::{
+    config::Config,
+    data::{Compound, World},
+};
+
+fn describe(item: &Compound) -> String {
+    let id = match item.get("id") {
+        Some(fastnbt::Value::String(id)) => id.as_str(),
+        _ => "unknown",
+    };
+    let count = item
+        .get("count")
+        .or_else(|| item.get("Count"))
+        .and_then(fastnbt::Value::as_i64)
+        .unwrap_or(1);
+    format!("{count}×{id}")
+}
+
+#[culpa::throws]
+#[tracing::instrument(name = "lost_and_found", skip_all)]
+pub(super) fn run(world: &World, config: &Config) {
+    let Some(lost_and_found) = &config.lost_and_found else {
+        return;
+    };
+
+    if let Some(dimension) = config.dimension.get(&lost_and_found.dimension) {
+        for chest in &lost_and_found.chests {
+            let chunk = chest.to_block_coord().block_to_chunk();
+            ensure!(
+                dimension.persistent.iter().any(|area| area.contains(chunk)),
+                "lost and found chest at {chest} is not in a persistent area"
+            );
+        }
+    }
+
+    let mut recovered = Vec::new();
+    let mut report_lines = Vec::new();
+    // chunks whose containers were recovered from, by dimension and region
+    let mut sources = BTreeMap::<_, Vec<_>>::new();
+    for (dimension_kind, dimension_config) in &config.dimension {
+        let _guard = tracing::info_span!("dimension", dimension.kind = %dimension_kind).entered();
+
+        let dimension = world.dimension(*dimension_kind);
+        let kept_chunks = dimension_config.kept_chunks();
+
+        for region in dimension.regions()? {
+            let mut region = region?;
+            let _guard = tracing::info_span!("region", region.coord = %region.coord).entered();
+            let all_chunks = Result::<BTreeSet<_>, _>::from_iter(region.chunks())?;
+            for chunk_coord in &all_chunks - &kept_chunks {
+                let Some(chunk) = region.chunk(chunk_coord)? else {
+                    continue;
+                };
+                let mut found = false;
+                for container in chunk.containers()? {
+                    if container.items.is_empty() {
+                        continue;
+                    }
+                    let position = format!("{},{},{}", container.x, container.y, container.z);
+                    let _guard = tracing::info_span!("container", container.id = %container.id, container.position = %position).entered();
+                    tracing::debug!("Recovering {} items", container.items.len());
+                    report_lines.push(format!(
+                        "{dimension_kind} {position} {}: {}",
+                        container.id,
+                        container.items.iter().map(describe).join(", ")
+                    ));
+                    recovered.extend(container.items);
+                    found = true;
+                }
+                if found {
+                    sources
+                        .entry((*dimension_kind, region.coord))
+                        .or_default()
+                        .push(chunk_coord);
+                }
+            }
+        }
+    }
+
+    // fill every chest in memory first, so nothing is written unless all the items fit
+    let recovered_count = recovered.len();
+    let mut dimension = world.dimension(lost_and_found.dimension);
+    let mut chest_chunks = BTreeMap::new();
+    let mut remaining = recovered;
+    for chest in &lost_and_found.chests {
+        if remaining.is_empty() {
+            break;
+        }
+        let _guard = tracing::info_span!("chest", chest.position = %chest).entered();
+        let coord = chest.to_block_coord();
+        let chunk_coord = coord.block_to_chunk();
+        let chunk = match chest_chunks.entry(chunk_coord) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(
+                dimension
+                    .cached_region_for_chunk(chunk_coord)?
+                    .context("missing lost and found chest region")?
+                    .chunk(chunk_coord)?
+                    .context("missing lost and found chest chunk")?,
+            ),
+        };
+        remaining = chunk.fill_container(coord, chest.block_y(), remaining)?;
+    }
+
+    // the chunks holding the items are about to be deleted, so stop rather than lose any
+    ensure!(
+        remaining.is_empty(),
+        "{} of {recovered_count} recovered items do not fit in the lost and found chests, add more chests: {}",
+        remaining.len(),
+        remaining.iter().map(describe).join(", ")
+    );
+
+    for (chunk_coord, chunk) in chest_chunks {
+        dimension
+            .cached_region_for_chunk(chunk_coord)?
+            .context("missing lost and found chest region")?
+            .save_chunk(&chunk)?;
+    }
+    dimension.flush()?;
+
+    if let Some(report) = &lost_and_found.report {
+        let mut report = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(world.directory.join(report))?;
+        for line in report_lines {
+            writeln!(report, "{line}")?;
+        }
+    }
+
+    // emptied only once the items are safely in the chests, so running the stage again doesn't
+    // recover them twice, a crash in between can only duplicate them rather than lose them
+    for ((dimension_kind, region_coord), chunk_coords) in sources {
+        let Some(mut region) = world.dimension(dimension_kind).region(region_coord)? else {
+            bail!("region disappeared while emptying recovered containers");
+        };
+        for chunk_coord in chunk_coords {
+            let mut chunk = region
+                .chunk(chunk_coord)?
+                .context("chunk disappeared while emptying recovered containers")?;
+            chunk.empty_containers()?;
+            region.save_chunk(&chunk)?;
+        }
+        region.flush()?;
+    }
+
+    tracing::info!("Recovered {recovered_count} items into lost and found");
+}
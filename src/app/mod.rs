@@ -1,22 +1,42 @@
-use camino::Utf8PathBuf;
-use eyre::Error;
+use camino::{Utf8Path, Utf8PathBuf};
+use eyre::{Context, Error};
+use indicatif::MultiProgress;
 use itertools::Itertools;
+use std::{num::NonZeroUsize, time::Instant};
 
 use crate::{
     config::{Config, OutOfBounds, PersistentArea},
-    data::{Coord, World},
+    data::{parse_seed, Coord, ServerProperties, World},
+    metrics,
+    report::{AddedArea, Report, Warnings},
 };
 
+use self::failures::{ErrorPolicy, Failures};
+
 mod force_blending;
 // mod print_blending;
+mod clean_saved_data;
+mod compact;
 mod delete_chunks;
+mod dragon_fight;
+mod edit_level;
+mod failures;
+mod fsck;
+mod invalidate_maps;
+mod jobs;
+mod lost_and_found;
 mod relocate_players;
 mod set_seed;
+mod stats;
 
 #[derive(Debug, clap::Parser)]
 pub(crate) struct App {
-    /// Path to world directory
-    world: Utf8PathBuf,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Path to world directory, or to a server directory containing server.properties
+    #[arg(required = true)]
+    world: Option<Utf8PathBuf>,
 
     /// Enable all stages
     #[arg(long)]
@@ -26,36 +46,141 @@ pub(crate) struct App {
     #[arg(long)]
     relocate_players: bool,
 
+    /// If a lost and found is configured, recover items from containers in chunks that will be
+    /// deleted
+    #[arg(long)]
+    lost_and_found: bool,
+
     /// Delete all chunks outside configured persistence areas
     #[arg(long)]
     delete_chunks: bool,
 
+    /// Remove saved data entries such as raids and forced chunks located in deleted chunks
+    #[arg(long)]
+    clean_saved_data: bool,
+
+    /// If map handling is configured, clear or archive maps showing deleted chunks
+    #[arg(long)]
+    invalidate_maps: bool,
+
     /// Force blending on all border chunks
     #[arg(long)]
     force_blending: bool,
 
-    /// Randomize the world seed
+    /// If level editing is configured, apply it to level.dat
+    #[arg(long)]
+    edit_level: bool,
+
+    /// Pick a new world seed according to the configured schedule, random by default
     #[arg(long)]
     randomize_seed: bool,
 
-    /// Set a particular world seed
-    #[arg(long, value_name = "SEED")]
+    /// Set a particular world seed, text that isn't a number is hashed the same way Minecraft does,
+    /// with `--all` it is set instead of a random one
+    #[arg(long, value_name = "SEED", value_parser = parse_seed, conflicts_with = "randomize_seed")]
     set_seed: Option<i64>,
 
+    /// What to do when deleting or blending hits a region or chunk it can't process, skipped and
+    /// quarantined ones are listed at the end and fail the run
+    #[arg(long, value_enum, default_value_t)]
+    on_error: ErrorPolicy,
+
+    /// How many regions to delete, cull or blend at once, logs are still written in region order
+    #[arg(long, short, default_value_t = NonZeroUsize::MIN)]
+    jobs: NonZeroUsize,
+
+    /// Write a JSON summary of the run to this file, covering what was deleted, blended and
+    /// relocated along with any warnings
+    #[arg(long, value_name = "PATH")]
+    report: Option<Utf8PathBuf>,
+
+    /// Write Prometheus metrics to this file once the run succeeds, for node_exporter's textfile
+    /// collector
+    #[arg(long, value_name = "PATH")]
+    metrics: Option<Utf8PathBuf>,
+
     /// Only verify the config file is correct
     #[arg(long)]
     verify_config: bool,
 }
 
+#[derive(Debug, clap::Subcommand)]
+enum Command {
+    /// Rewrite every region file with its chunks packed tightly, reclaiming the space left behind
+    /// by deleted chunks
+    Compact {
+        /// Path to world directory, or to a server directory containing server.properties
+        world: Utf8PathBuf,
+    },
+
+    /// Check every region, entity and poi file for corrupt headers and chunks
+    Fsck {
+        /// Path to world directory, or to a server directory containing server.properties
+        world: Utf8PathBuf,
+
+        /// Drop broken chunks, and move chunks stored in the wrong slot to the right one
+        #[arg(long)]
+        repair: bool,
+    },
+
+    /// Show how much disk space each dimension and persistent area uses, and how much deleting
+    /// chunks would free
+    Stats {
+        /// Path to world directory, or to a server directory containing server.properties
+        world: Utf8PathBuf,
+    },
+}
+
+impl Command {
+    #[culpa::throws]
+    fn run(self) {
+        match self {
+            Command::Compact { world } => compact::run(&open_world(&world)?.0)?,
+            Command::Fsck { world, repair } => fsck::run(&open_world(&world)?.0, repair)?,
+            Command::Stats { world } => {
+                let (world, _) = open_world(&world)?;
+                let config = Config::load(&world.directory.join("fc5-tool.toml"))?;
+                stats::run(&world, &config)?;
+            }
+        }
+    }
+}
+
+/// Opens the world at a path, or the world a server directory's server.properties points at
+#[culpa::throws]
+fn open_world(path: &Utf8Path) -> (World, Option<ServerProperties>) {
+    let server = ServerProperties::load(&path.join("server.properties"))?;
+    let world = match &server {
+        Some(server) => {
+            // minecraft falls back to "world" if the property is missing
+            let level_name = server.get("level-name").unwrap_or("world");
+            tracing::info!(server.properties = %server.path, level.name = level_name, "Using world from server properties");
+            World::new(&path.join(level_name))
+        }
+        None => World::new(path),
+    };
+    (world, server)
+}
+
 impl App {
     #[culpa::throws]
-    pub(super) fn run(self) {
-        let world = World::new(&self.world);
-        let mut config = Config::load(&self.world.join("fc5-tool.toml"))?;
+    pub(super) fn run(mut self, bars: &MultiProgress, warnings: &Warnings) {
+        if let Some(command) = self.command.take() {
+            return command.run()?;
+        }
+
+        let (world, mut server) = open_world(
+            self.world
+                .as_deref()
+                .expect("world is required without a subcommand"),
+        )?;
+        let mut config = Config::load(&world.directory.join("fc5-tool.toml"))?;
         if self.verify_config {
             return;
         }
 
+        let mut report = Report::new()?;
+
         if let Some(OutOfBounds::PersistChunks { size, blending }) = config.players.out_of_bounds {
             let radius = (size.max(1) / 2).into();
             let offset = Coord {
@@ -94,6 +219,13 @@ impl App {
                 let _guard = tracing::info_span!("area", new_area.top_left = %top_left, new_area.bottom_right = %bottom_right).entered();
                 tracing::info!("Player is out-of-bounds, adding persistent area");
 
+                report.added_areas.push(AddedArea {
+                    player: uuid.to_string(),
+                    dimension: dimension_kind,
+                    top_left,
+                    bottom_right,
+                });
+
                 Ok::<_, Error>(Some((dimension_kind, PersistentArea::Square { top_left, bottom_right, blending })))
             }).filter_map(Result::transpose))?;
 
@@ -107,24 +239,114 @@ impl App {
             }
         }
 
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(self.jobs.get())
+            .build_global()
+            .context("starting job threads")?;
+
+        let mut failures = Failures::new(self.on_error);
+        let result = self.run_stages(
+            &world,
+            &config,
+            server.as_mut(),
+            &mut failures,
+            bars,
+            &mut report,
+        );
+
+        // what was skipped is listed, and the report written, even when a later stage failed
+        report.skipped = failures.skipped().to_vec();
+        let result = result.and(failures.finish());
+
+        if let Some(path) = &self.report {
+            let saved = report.save(path, warnings, result.as_ref().err());
+            if let (Err(_), Err(error)) = (&result, &saved) {
+                tracing::error!("Failed to write report: {error:#}");
+            }
+            result?;
+            saved?;
+        } else {
+            result?;
+        }
+
+        if let Some(path) = &self.metrics {
+            metrics::write(path, &report, &config)?;
+        }
+    }
+
+    /// Runs the enabled stages in order
+    #[culpa::throws]
+    fn run_stages(
+        &self,
+        world: &World,
+        config: &Config,
+        server: Option<&mut ServerProperties>,
+        failures: &mut Failures,
+        bars: &MultiProgress,
+        report: &mut Report,
+    ) {
         if self.all || self.relocate_players {
-            relocate_players::run(&world, &config)?;
+            let started = Instant::now();
+            relocate_players::run(world, config, report)?;
+            report.stage_done("relocate-players", started);
+        }
+
+        if self.all || self.lost_and_found {
+            let started = Instant::now();
+            lost_and_found::run(world, config)?;
+            report.stage_done("lost-and-found", started);
         }
 
         if self.all || self.delete_chunks {
-            delete_chunks::run(&world, &config)?;
+            let started = Instant::now();
+            delete_chunks::run(world, config, failures, bars, report)?;
+            dragon_fight::run(world, config)?;
+            report.stage_done("delete-chunks", started);
+        }
+
+        if self.all || self.clean_saved_data {
+            let started = Instant::now();
+            clean_saved_data::run(world, config)?;
+            report.stage_done("clean-saved-data", started);
+        }
+
+        if self.all || self.invalidate_maps {
+            let started = Instant::now();
+            invalidate_maps::run(world, config)?;
+            report.stage_done("invalidate-maps", started);
         }
 
         if self.all || self.force_blending {
-            force_blending::run(&world, &config)?;
+            let started = Instant::now();
+            force_blending::run(world, config, failures, bars, report)?;
+            report.stage_done("force-blending", started);
         }
 
-        if self.all || self.randomize_seed {
-            set_seed::run(&world, rand::random())?;
+        if self.all || self.edit_level {
+            let started = Instant::now();
+            edit_level::run(world, config)?;
+            report.stage_done("edit-level", started);
         }
 
-        if let (Some(seed), false) = (self.set_seed, self.randomize_seed) {
-            set_seed::run(&world, seed)?;
+        let sync_server = match (server, config.seed.sync_server_properties) {
+            (Some(server), true) => Some(server),
+            (None, true) => {
+                tracing::warn!("Syncing seed to server properties is configured, but no server.properties was found");
+                None
+            }
+            (_, false) => None,
+        };
+
+        // every seed set is recorded in the history, so only ever set one
+        if let Some(seed) = self.set_seed {
+            let started = Instant::now();
+            set_seed::run(world, sync_server, seed, report)?;
+            report.stage_done("set-seed", started);
+        } else if self.all || self.randomize_seed {
+            let started = Instant::now();
+            let seed = set_seed::next(world, config)?;
+            set_seed::run(world, sync_server, seed, report)?;
+            report.stage_done("randomize-seed", started);
         }
     }
 }
@@ -1,14 +1,22 @@
-use eyre::Error;
+use eyre::{bail, Error};
+use std::collections::{hash_map::Entry, HashMap, HashSet};
+use uuid::Uuid;
 
 use crate::{
-    config::{Config, OutOfBounds, PersistentArea},
-    data::World,
+    config::{self, Config, Note, OutOfBounds, PersistentArea, Relocate, Vehicles},
+    data::{Chunk, Coord, Coord3, World},
+    report::{RelocatedPlayer, Report},
 };
 
+const DEFAULT_SEARCH_RADIUS: u32 = 16;
+
 #[culpa::throws]
 #[tracing::instrument(name = "relocate", skip_all)]
-pub(super) fn run(world: &World, config: &Config) {
-    if let Some(OutOfBounds::Relocate(relocate)) = config.players.out_of_bounds {
+pub(super) fn run(world: &World, config: &Config, report: &mut Report) {
+    if let Some(OutOfBounds::Relocate(relocate)) = &config.players.out_of_bounds {
+        let mut landing = None;
+        let mut relocated = HashSet::new();
+
         'players: for uuid in world.players()? {
             let uuid = uuid?;
 
@@ -39,14 +47,374 @@ pub(super) fn run(world: &World, config: &Config) {
             }
 
             let new_dimension = relocate.dimension;
-            let new_position = relocate.position;
+            let new_position = match landing {
+                Some(landing) => landing,
+                None => *landing.insert(find_landing(world, config, relocate)?),
+            };
 
             let _guard = tracing::info_span!("to", new.dimension = %new_dimension, new.position = %new_position).entered();
             player.set_dimension(new_dimension)?;
             player.set_position(new_position)?;
+            player.reset_motion()?;
+
+            if let Some(vehicle) = player.vehicle()? {
+                let _guard = tracing::info_span!("vehicle", vehicle.id = %vehicle).entered();
+                match relocate.vehicles {
+                    Vehicles::Move => {
+                        player.set_vehicle_position(new_position)?;
+                        tracing::info!("Moving vehicle with player");
+                    }
+                    Vehicles::Dismount => {
+                        player.dismount()?;
+                        tracing::warn!("Dismounting player, vehicle will be discarded");
+                    }
+                }
+            }
+
+            if let Some(note) = &relocate.note {
+                let given = match note {
+                    Note::Book {
+                        title,
+                        author,
+                        text,
+                    } => {
+                        let text = text
+                            .replace("{old-dimension}", &old_dimension.to_string())
+                            .replace("{old-position}", &old_position.to_block_coord().to_string())
+                            .replace("{new-dimension}", &new_dimension.to_string())
+                            .replace("{new-position}", &new_position.to_block_coord().to_string());
+                        let pages = Vec::from_iter(text.split("\n\n").map(str::to_owned));
+                        player.give_book(title, author, &pages)?
+                    }
+                    Note::Item { id } => player.give_item(id)?,
+                };
+                if given {
+                    tracing::info!("Gave player a note");
+                } else {
+                    tracing::warn!("Player's inventory is full, could not give them a note");
+                }
+            }
 
             world.save_player(&player)?;
             tracing::info!("Relocated player");
+            relocated.insert(uuid);
+            report.relocated_players.push(RelocatedPlayer {
+                uuid: uuid.to_string(),
+                old_dimension,
+                old_position,
+                new_dimension,
+                new_position,
+            });
+        }
+
+        if let (true, Some(landing)) = (relocate.pets, landing) {
+            relocate_pets(world, config, relocate, landing, &relocated)?;
+        }
+    }
+}
+
+/// Moves tamed animals owned by relocated players out of chunks that will be regenerated
+#[culpa::throws]
+#[tracing::instrument(name = "pets", skip_all, fields(new.dimension = %relocate.dimension, new.position = %landing))]
+fn relocate_pets(
+    world: &World,
+    config: &Config,
+    relocate: &Relocate,
+    landing: Coord3,
+    owners: &HashSet<Uuid>,
+) {
+    let mut pets = Vec::new();
+    let mut data_version = None;
+    let mut sources = Vec::new();
+    for (dimension_kind, config::Dimension { persistent }) in &config.dimension {
+        let _guard = tracing::info_span!("dimension", dimension.kind = %dimension_kind).entered();
+        let dimension = world.dimension(*dimension_kind);
+        for region in dimension.entity_regions()? {
+            let mut region = region?;
+            let _guard = tracing::info_span!("region", region.coord = %region.coord).entered();
+            let chunks = Result::<Vec<_>, _>::from_iter(region.chunks())?;
+            for chunk_coord in chunks {
+                if persistent.iter().any(|area| area.contains(chunk_coord)) {
+                    continue;
+                }
+                let _guard =
+                    tracing::info_span!("chunk", entity_chunk.absolute_coord = %chunk_coord)
+                        .entered();
+                let Some(mut chunk) = region.chunk(chunk_coord)? else {
+                    continue;
+                };
+                let owned = chunk.take_owned_entities(owners)?;
+                if owned.is_empty() {
+                    continue;
+                }
+                tracing::info!("Found {} pets", owned.len());
+                data_version = data_version.or_else(|| chunk.data.get("DataVersion").cloned());
+                pets.extend(owned);
+                sources.push((dimension_kind, region.coord, chunk));
+            }
+        }
+    }
+
+    let Some(data_version) = data_version else {
+        tracing::info!("No pets to relocate");
+        return;
+    };
+
+    // write the pets to their new home before removing them from the old one, so that a crash
+    // can only duplicate them rather than lose them
+    let pet_count = pets.len();
+    {
+        let coord = landing.to_block_coord().block_to_chunk();
+        let dimension = world.dimension(relocate.dimension);
+        let mut region = dimension.entity_region_or_create(coord.chunk_to_region())?;
+        let mut chunk = match region.chunk(coord)? {
+            Some(chunk) => chunk,
+            None => region.new_entity_chunk(coord, data_version)?,
+        };
+        chunk.add_entities(pets, landing)?;
+        region.save_chunk(&chunk)?;
+        region.flush()?;
+    }
+
+    for (dimension_kind, region_coord, chunk) in sources {
+        let Some(mut region) = world
+            .dimension(*dimension_kind)
+            .entity_region(region_coord)?
+        else {
+            bail!("entity region disappeared while relocating pets");
+        };
+        region.save_chunk(&chunk)?;
+        region.flush()?;
+    }
+
+    tracing::info!("Relocated {pet_count} pets");
+}
+
+fn is_air(block: &str) -> bool {
+    matches!(
+        block,
+        "minecraft:air" | "minecraft:cave_air" | "minecraft:void_air"
+    )
+}
+
+fn is_hazard(block: &str) -> bool {
+    matches!(
+        block,
+        "minecraft:lava"
+            | "minecraft:fire"
+            | "minecraft:soul_fire"
+            | "minecraft:magma_block"
+            | "minecraft:campfire"
+            | "minecraft:soul_campfire"
+            | "minecraft:cactus"
+            | "minecraft:sweet_berry_bush"
+            | "minecraft:wither_rose"
+            | "minecraft:powder_snow"
+            | "minecraft:pointed_dripstone"
+    )
+}
+
+/// Blocks without collision, or too thin to stand on, that a player would fall or sink through
+fn is_passable(block: &str) -> bool {
+    let name = block.strip_prefix("minecraft:").unwrap_or(block);
+    [
+        "_sign",
+        "_banner",
+        "_carpet",
+        "_button",
+        "_pressure_plate",
+        "_sapling",
+        "_tulip",
+        "_mushroom",
+        "_fungus",
+        "_coral",
+        "_coral_fan",
+        "_coral_wall_fan",
+        "torch",
+        "rail",
+        "vines",
+    ]
+    .iter()
+    .any(|suffix| name.ends_with(suffix))
+        || matches!(
+            name,
+            "water"
+                | "bubble_column"
+                | "grass"
+                | "short_grass"
+                | "tall_grass"
+                | "fern"
+                | "large_fern"
+                | "dead_bush"
+                | "seagrass"
+                | "tall_seagrass"
+                | "kelp"
+                | "kelp_plant"
+                | "vine"
+                | "snow"
+                | "dandelion"
+                | "poppy"
+                | "blue_orchid"
+                | "allium"
+                | "azure_bluet"
+                | "oxeye_daisy"
+                | "cornflower"
+                | "lily_of_the_valley"
+                | "torchflower"
+                | "pink_petals"
+                | "sunflower"
+                | "lilac"
+                | "rose_bush"
+                | "peony"
+                | "pitcher_plant"
+                | "spore_blossom"
+                | "crimson_roots"
+                | "warped_roots"
+                | "hanging_roots"
+                | "nether_sprouts"
+                | "glow_lichen"
+                | "sculk_vein"
+                | "sugar_cane"
+                | "wheat"
+                | "carrots"
+                | "potatoes"
+                | "beetroots"
+                | "nether_wart"
+                | "lily_pad"
+                | "ladder"
+                | "scaffolding"
+                | "cobweb"
+                | "lever"
+                | "tripwire"
+                | "tripwire_hook"
+                | "redstone_wire"
+                | "light"
+                | "structure_void"
+                | "nether_portal"
+                | "end_portal"
+                | "end_gateway"
+        )
+}
+
+fn is_solid_floor(block: &str) -> bool {
+    !is_air(block) && !is_hazard(block) && !is_passable(block)
+}
+
+/// Checks for a solid floor below the given block, and two air blocks to stand in
+#[culpa::throws]
+fn is_safe(chunk: &Chunk, x: usize, y: i64, z: usize) -> bool {
+    let (Some(floor), Some(feet), Some(head)) = (
+        chunk.block(x, y - 1, z)?,
+        chunk.block(x, y, z)?,
+        chunk.block(x, y + 1, z)?,
+    ) else {
+        return false;
+    };
+    is_solid_floor(floor) && is_air(feet) && is_air(head)
+}
+
+/// Block columns in rings of increasing distance around the center
+fn spiral(center: Coord<i64>, radius: i64) -> impl Iterator<Item = Coord<i64>> {
+    (0..=radius).flat_map(move |ring| {
+        (-ring..=ring)
+            .flat_map(move |x| (-ring..=ring).map(move |z| Coord { x, z }))
+            .filter(move |offset| offset.x.abs().max(offset.z.abs()) == ring)
+            .map(move |offset| Coord {
+                x: center.x + offset.x,
+                z: center.z + offset.z,
+            })
+    })
+}
+
+/// Heights in order of increasing distance from the center
+fn heights(center: i64, radius: i64) -> impl Iterator<Item = i64> {
+    std::iter::once(center).chain((1..=radius).flat_map(move |d| [center + d, center - d]))
+}
+
+#[culpa::throws]
+#[tracing::instrument(name = "landing", skip_all, fields(dimension.kind = %relocate.dimension, position = %relocate.position))]
+fn find_landing(world: &World, config: &Config, relocate: &Relocate) -> Coord3 {
+    let mut dimension = world.dimension(relocate.dimension);
+    let persistent = config
+        .dimension
+        .get(&relocate.dimension)
+        .map(|dimension| &dimension.persistent);
+    let radius = i64::from(relocate.search_radius.unwrap_or(DEFAULT_SEARCH_RADIUS));
+    let origin = relocate.position.to_block_coord();
+
+    let mut chunks = HashMap::new();
+    for column in spiral(origin, radius) {
+        let chunk_coord = column.block_to_chunk();
+        if let Some(persistent) = persistent {
+            if !persistent.iter().any(|area| area.contains(chunk_coord)) {
+                continue;
+            }
+        }
+
+        let chunk = match chunks.entry(chunk_coord) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                entry.insert(match dimension.cached_region_for_chunk(chunk_coord)? {
+                    Some(region) => region.chunk(chunk_coord)?,
+                    None => None,
+                })
+            }
+        };
+        let Some(chunk) = chunk else {
+            continue;
+        };
+
+        let x = usize::try_from(column.x.rem_euclid(16))?;
+        let z = usize::try_from(column.z.rem_euclid(16))?;
+        for y in heights(relocate.position.block_y(), radius) {
+            if is_safe(chunk, x, y, z)? {
+                if column == origin && y == relocate.position.block_y() {
+                    tracing::debug!("Configured position is safe");
+                    return relocate.position;
+                }
+                let landing = Coord3::from_block(column, y);
+                tracing::info!(landing = %landing, "Configured position is unsafe, found safe landing nearby");
+                return landing;
+            }
+        }
+    }
+
+    bail!(
+        "no safe landing spot within {radius} blocks of {} in persistent areas",
+        relocate.position
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_solid_floor;
+
+    #[test]
+    fn solid_floor() {
+        for block in [
+            "minecraft:stone",
+            "minecraft:grass_block",
+            "minecraft:snow_block",
+            "minecraft:mangrove_roots",
+            "minecraft:red_mushroom_block",
+            "minecraft:tube_coral_block",
+        ] {
+            assert!(is_solid_floor(block), "{block}");
+        }
+        for block in [
+            "minecraft:oak_sign",
+            "minecraft:spruce_wall_hanging_sign",
+            "minecraft:poppy",
+            "minecraft:red_tulip",
+            "minecraft:snow",
+            "minecraft:white_carpet",
+            "minecraft:moss_carpet",
+            "minecraft:soul_wall_torch",
+            "minecraft:powered_rail",
+            "minecraft:weeping_vines",
+            "minecraft:lava",
+        ] {
+            assert!(!is_solid_floor(block), "{block}");
         }
     }
 }
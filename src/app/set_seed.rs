@@ -1,10 +1,102 @@
-use eyre::{bail, Error};
+use eyre::{bail, ensure, Context, ContextCompat, Error};
+use std::{collections::HashSet, time::SystemTime};
 
-use crate::data::World;
+use crate::{
+    config::{Config, Schedule},
+    data::{Compound, SeedRecord, ServerProperties, World},
+    report::{Report, SeedChange},
+};
 
+/// Mixes the base seed and cycle with SplitMix64, so that consecutive cycles get unrelated seeds
+fn derive(base: i64, cycle: u64) -> i64 {
+    let mut z = 0u64
+        .wrapping_add_signed(base)
+        .wrapping_add(cycle.wrapping_mul(0x9e37_79b9_7f4a_7c15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    i64::from_ne_bytes((z ^ (z >> 31)).to_ne_bytes())
+}
+
+/// Picks the seed for the next cycle according to the configured schedule
+#[culpa::throws]
+#[tracing::instrument(name = "next_seed", skip_all)]
+pub(super) fn next(world: &World, config: &Config) -> i64 {
+    let history = world.seed_history()?;
+    let cycle = history.last().map_or(1, |record| record.cycle + 1);
+    let _guard = tracing::info_span!("cycle", seed.cycle = cycle).entered();
+    match &config.seed.schedule {
+        Schedule::Random => {
+            let used = HashSet::<i64>::from_iter(history.iter().map(|record| record.seed));
+            std::iter::repeat_with(rand::random::<i64>)
+                .find(|seed| !used.contains(seed))
+                .expect("infinite iterator")
+        }
+        Schedule::List(seeds) => *usize::try_from(cycle - 1)
+            .ok()
+            .and_then(|index| seeds.get(index))
+            .with_context(|| format!("seed schedule has no seed for cycle {cycle}"))?,
+        Schedule::Derived { base } => derive(*base, cycle),
+    }
+}
+
+/// Finds every seed stored in the world generation settings, along with its path for reporting
+///
+/// Worlds created before 1.19 also store the seed in each dimension's generator and biome source,
+/// these must all be kept consistent
 #[culpa::throws]
-#[tracing::instrument(name = "set_seed", skip_all)]
-pub(super) fn run(world: &World, set_seed: i64) {
+fn seeds(settings: &mut Compound) -> Vec<(String, &mut i64)> {
+    let mut seeds = Vec::new();
+    let mut dimensions = None;
+    for (key, value) in settings.iter_mut() {
+        match (key.as_str(), value) {
+            ("seed", fastnbt::Value::Long(seed)) => seeds.push(("seed".to_owned(), seed)),
+            ("dimensions", fastnbt::Value::Compound(value)) => dimensions = Some(value),
+            ("seed" | "dimensions", _) => bail!("bad {key}"),
+            _ => {}
+        }
+    }
+    ensure!(!seeds.is_empty(), "missing seed");
+
+    for (name, dimension) in dimensions.context("missing dimensions")? {
+        let fastnbt::Value::Compound(dimension) = dimension else {
+            bail!("bad dimensions.{name}")
+        };
+        let Some(fastnbt::Value::Compound(generator)) = dimension.get_mut("generator") else {
+            bail!("bad dimensions.{name}.generator")
+        };
+        for (key, value) in generator.iter_mut() {
+            match (key.as_str(), value) {
+                ("seed", fastnbt::Value::Long(seed)) => {
+                    seeds.push((format!("dimensions.{name}.generator.seed"), seed));
+                }
+                ("biome_source", fastnbt::Value::Compound(biome_source)) => {
+                    match biome_source.get_mut("seed") {
+                        Some(fastnbt::Value::Long(seed)) => seeds.push((
+                            format!("dimensions.{name}.generator.biome_source.seed"),
+                            seed,
+                        )),
+                        Some(_) => bail!("bad dimensions.{name}.generator.biome_source.seed"),
+                        None => {}
+                    }
+                }
+                ("seed" | "biome_source", _) => bail!("bad dimensions.{name}.generator.{key}"),
+                _ => {}
+            }
+        }
+    }
+
+    seeds.sort_by(|(a, _), (b, _)| a.cmp(b));
+    seeds
+}
+
+#[culpa::throws]
+#[tracing::instrument(name = "set_seed", skip_all, fields(new.seed = set_seed))]
+pub(super) fn run(
+    world: &World,
+    server: Option<&mut ServerProperties>,
+    set_seed: i64,
+    report: &mut Report,
+) {
     let mut level = world.level()?;
     let Some(fastnbt::Value::Compound(data)) = level.get_mut("Data") else {
         bail!("bad Data")
@@ -12,12 +104,48 @@ pub(super) fn run(world: &World, set_seed: i64) {
     let Some(fastnbt::Value::Compound(settings)) = data.get_mut("WorldGenSettings") else {
         bail!("bad WorldGenSettings")
     };
-    let Some(fastnbt::Value::Long(seed)) = settings.get_mut("seed") else {
-        bail!("bad seed")
-    };
-    let _guard = tracing::info_span!("from", old.seed = %seed).entered();
-    *seed = set_seed;
-    let _guard = tracing::info_span!("to", new.seed = %seed).entered();
+
+    // find every seed before changing any, so an unrecognised layout leaves the world untouched
+    let seeds = seeds(settings).context("unrecognised WorldGenSettings layout")?;
+    let seed_count = seeds.len();
+    let mut old_seed = None;
+    for (field, seed) in seeds {
+        let _guard = tracing::info_span!("field", seed.field = %field, old.seed = %seed).entered();
+        if field == "seed" {
+            old_seed = Some(*seed);
+        }
+        *seed = set_seed;
+        tracing::info!("Set seed");
+    }
+
     world.save_level(&level)?;
-    tracing::info!("Set seed");
+    tracing::info!("Set {seed_count} seeds");
+
+    let cycle = world
+        .seed_history()?
+        .last()
+        .map_or(1, |record| record.cycle + 1);
+    let timestamp = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)?
+        .as_secs();
+    world.record_seed(&SeedRecord {
+        cycle,
+        timestamp,
+        seed: set_seed,
+    })?;
+    tracing::info!(seed.cycle = cycle, "Recorded seed in history");
+    report.seed = Some(SeedChange {
+        old: old_seed,
+        new: set_seed,
+        cycle,
+    });
+
+    if let Some(server) = server {
+        let _guard =
+            tracing::info_span!("server", server.properties = %server.path, old.seed = server.get("level-seed"))
+                .entered();
+        server.set("level-seed", &set_seed.to_string());
+        server.save()?;
+        tracing::info!("Set level-seed");
+    }
 }
@@ -0,0 +1,270 @@
+use camino::Utf8PathBuf;
+use eyre::{Error, Result};
+use indicatif::HumanBytes;
+use std::collections::BTreeSet;
+
+use crate::{
+    config::{Config, PersistentArea},
+    data::{
+        dimension::{self, file_len, ExternalChunkFiles},
+        Coord, Dimension, Region, World,
+    },
+};
+
+/// Where the bytes of one directory of region files go
+#[derive(Debug, PartialEq)]
+struct Usage {
+    /// Bytes of the chunks inside each persistent area, in config order, chunks in overlapping
+    /// areas count towards each of them
+    areas: Vec<u64>,
+    /// Bytes deleting chunks would free, including what compacting the kept regions reclaims
+    freed: u64,
+    /// Bytes of kept regions that couldn't be read, and of their external chunk files, so can't be
+    /// attributed either way
+    unreadable: u64,
+}
+
+impl Usage {
+    /// Counts a chunk's bytes towards the areas it is in, or towards what a reset frees
+    fn attribute(&mut self, persistent: &[PersistentArea], chunk_coord: Coord<i64>, len: u64) {
+        let mut kept = false;
+        for (area, area_len) in persistent.iter().zip(&mut self.areas) {
+            if area.contains(chunk_coord) {
+                *area_len += len;
+                kept = true;
+            }
+        }
+        if !kept {
+            self.freed += len;
+        }
+    }
+}
+
+/// Attributes the bytes of a directory of regions to persistent areas, or to what a reset frees
+///
+/// Regions outside persistent areas are only measured, not opened, and the external files of
+/// oversized chunks count towards their chunk.
+#[culpa::throws]
+fn usage(
+    dimension: &Dimension,
+    coords: impl Iterator<Item = Result<Coord<i64>>>,
+    path: fn(&Dimension, Coord<i64>) -> Utf8PathBuf,
+    open: fn(&Dimension, Coord<i64>) -> Result<Option<Region>>,
+    external_files: &ExternalChunkFiles,
+    persistent: &[PersistentArea],
+    kept_regions: &BTreeSet<Coord<i64>>,
+) -> Usage {
+    let mut usage = Usage {
+        areas: vec![0; persistent.len()],
+        freed: 0,
+        unreadable: 0,
+    };
+    for region_coord in coords {
+        let region_coord = region_coord?;
+        let _guard = tracing::info_span!("region", region.coord = %region_coord).entered();
+        let region_path = path(dimension, region_coord);
+        let external_files = external_files
+            .get(&region_coord)
+            .map_or(&[][..], Vec::as_slice);
+
+        if !kept_regions.contains(&region_coord) {
+            usage.freed += file_len(&region_path)?;
+            for (_, external_path) in external_files {
+                usage.freed += file_len(external_path)?;
+            }
+            continue;
+        }
+
+        let region = match open(dimension, region_coord) {
+            Ok(Some(region)) => region,
+            Ok(None) => continue,
+            Err(error) => {
+                tracing::warn!("Unreadable region, counting it as a whole: {error:#}");
+                usage.unreadable += file_len(&region_path)?;
+                for (_, external_path) in external_files {
+                    usage.unreadable += file_len(external_path)?;
+                }
+                continue;
+            }
+        };
+        for chunk_len in region.chunk_lens() {
+            let (chunk_coord, len) = chunk_len?;
+            usage.attribute(persistent, chunk_coord, len);
+        }
+        for (chunk_coord, external_path) in external_files {
+            usage.attribute(persistent, *chunk_coord, file_len(external_path)?);
+        }
+        usage.freed += region.unused_len()?;
+    }
+    usage
+}
+
+#[culpa::throws]
+#[tracing::instrument(name = "stats", skip_all)]
+pub(super) fn run(world: &World, config: &Config) {
+    let mut total_freed = 0;
+    for dimension_kind in dimension::Kind::ALL {
+        let _guard = tracing::info_span!("dimension", dimension.kind = %dimension_kind).entered();
+
+        let dimension = world.dimension(dimension_kind);
+        tracing::info!(
+            "region/ holds {}, entities/ {}, poi/ {}",
+            HumanBytes(dimension.directory_len("region")?),
+            HumanBytes(dimension.directory_len("entities")?),
+            HumanBytes(dimension.directory_len("poi")?),
+        );
+
+        let Some(dimension_config) = config.dimension.get(&dimension_kind) else {
+            tracing::info!("Dimension is disabled, a reset leaves it alone");
+            continue;
+        };
+        let persistent = &dimension_config.persistent;
+        let kept_regions = dimension_config.kept_regions();
+
+        let chunks = usage(
+            &dimension,
+            dimension.region_coords()?,
+            Dimension::region_path,
+            Dimension::region,
+            &dimension.external_chunk_files()?,
+            persistent,
+            &kept_regions,
+        )?;
+        let entities = usage(
+            &dimension,
+            dimension.entity_region_coords()?,
+            Dimension::entity_region_path,
+            Dimension::entity_region,
+            &dimension.entity_external_chunk_files()?,
+            persistent,
+            &kept_regions,
+        )?;
+        for (area, (chunk_len, entity_len)) in persistent
+            .iter()
+            .zip(chunks.areas.iter().zip(&entities.areas))
+        {
+            let PersistentArea::Square {
+                top_left,
+                bottom_right,
+                ..
+            } = area;
+            let _guard = tracing::info_span!("persistent", area.top_left = %top_left, area.bottom_right = %bottom_right).entered();
+            tracing::info!(
+                "Area holds {} of chunks and {} of entities",
+                HumanBytes(*chunk_len),
+                HumanBytes(*entity_len)
+            );
+        }
+
+        // entities are only deleted when culling is configured, poi is never touched
+        let mut freed = chunks.freed;
+        if config.entities.cull {
+            freed += entities.freed;
+        }
+        tracing::info!("A reset would free {}", HumanBytes(freed));
+        let unreadable = chunks.unreadable + entities.unreadable;
+        if unreadable > 0 {
+            tracing::warn!(
+                "{} of kept regions could not be read, it is in neither the areas nor what a reset frees",
+                HumanBytes(unreadable)
+            );
+        }
+        total_freed += freed;
+    }
+
+    tracing::info!("A reset would free {} in total", HumanBytes(total_freed));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{usage, Usage};
+    use crate::{
+        config::PersistentArea,
+        data::{dimension, test_directory, Coord, Dimension, World},
+    };
+    use eyre::Error;
+    use pretty_assertions::assert_eq;
+    use std::collections::BTreeSet;
+
+    #[test]
+    #[culpa::throws]
+    fn usage_attributes_chunks() {
+        let directory = test_directory("stats-usage")?;
+        let world = World::new(&directory);
+        let dimension = world.dimension(dimension::Kind::Overworld);
+
+        // a kept region with one chunk in the area and one outside it, which is oversized
+        let mut kept = dimension.entity_region_or_create(Coord { x: 0, z: 0 })?;
+        for coord in [Coord { x: 0, z: 0 }, Coord { x: 1, z: 0 }] {
+            let chunk = kept.new_entity_chunk(coord, fastnbt::Value::Int(3700))?;
+            kept.save_chunk(&chunk)?;
+        }
+        drop(kept);
+        std::fs::write(directory.join("entities/c.1.0.mcc"), [0; 100])?;
+
+        // and a region outside any area
+        let mut stale = dimension.entity_region_or_create(Coord { x: 1, z: 0 })?;
+        let chunk = stale.new_entity_chunk(Coord { x: 32, z: 0 }, fastnbt::Value::Int(3700))?;
+        stale.save_chunk(&chunk)?;
+        drop(stale);
+
+        let persistent = [PersistentArea::Square {
+            top_left: Coord { x: 0, z: 0 },
+            bottom_right: Coord { x: 0, z: 0 },
+            blending: None,
+        }];
+        assert_eq!(
+            usage(
+                &dimension,
+                dimension.entity_region_coords()?,
+                Dimension::entity_region_path,
+                Dimension::entity_region,
+                &dimension.entity_external_chunk_files()?,
+                &persistent,
+                &BTreeSet::from([Coord { x: 0, z: 0 }]),
+            )?,
+            Usage {
+                areas: vec![4096],
+                freed: 4096 + 100 + 3 * 4096,
+                unreadable: 0,
+            }
+        );
+        std::fs::remove_dir_all(&directory)?;
+    }
+
+    #[test]
+    #[culpa::throws]
+    fn unreadable_region_counts_external_files() {
+        let directory = test_directory("stats-unreadable")?;
+        let world = World::new(&directory);
+        let dimension = world.dimension(dimension::Kind::Overworld);
+
+        std::fs::create_dir_all(directory.join("entities"))?;
+        // too short to hold a header
+        std::fs::write(directory.join("entities/r.0.0.mca"), [0; 100])?;
+        std::fs::write(directory.join("entities/c.1.0.mcc"), [0; 20])?;
+
+        let persistent = [PersistentArea::Square {
+            top_left: Coord { x: 0, z: 0 },
+            bottom_right: Coord { x: 0, z: 0 },
+            blending: None,
+        }];
+        assert_eq!(
+            usage(
+                &dimension,
+                dimension.entity_region_coords()?,
+                Dimension::entity_region_path,
+                Dimension::entity_region,
+                &dimension.entity_external_chunk_files()?,
+                &persistent,
+                &BTreeSet::from([Coord { x: 0, z: 0 }]),
+            )?,
+            Usage {
+                areas: vec![0],
+                freed: 0,
+                unreadable: 120,
+            }
+        );
+        std::fs::remove_dir_all(&directory)?;
+    }
+}
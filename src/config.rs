@@ -1,8 +1,8 @@
 use camino::Utf8Path;
 use eyre::{ensure, Error};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 
-use crate::data::{dimension, Coord, Coord3};
+use crate::data::{dimension, parse_seed, Coord, Coord3};
 
 #[derive(Clone, PartialEq, Debug, serde::Deserialize)]
 #[serde(rename_all = "kebab-case")]
@@ -13,6 +13,18 @@ pub(crate) struct Config {
     #[serde(default)]
     pub(crate) entities: Entities,
 
+    #[serde(default)]
+    pub(crate) lost_and_found: Option<LostAndFound>,
+
+    #[serde(default)]
+    pub(crate) seed: Seed,
+
+    #[serde(default)]
+    pub(crate) level: Option<Level>,
+
+    #[serde(default)]
+    pub(crate) maps: Option<Maps>,
+
     #[serde(default)]
     pub(crate) dimension: HashMap<dimension::Kind, Dimension>,
 }
@@ -43,7 +55,7 @@ pub(crate) struct Players {
 }
 
 #[serde_with::serde_as]
-#[derive(Copy, Clone, PartialEq, Debug, serde::Deserialize)]
+#[derive(Clone, PartialEq, Debug, serde::Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub(crate) enum OutOfBounds {
     /// Re-locate players to persistent chunks,
@@ -64,11 +76,56 @@ pub(crate) enum OutOfBounds {
     },
 }
 
-#[derive(Copy, Clone, PartialEq, Debug, serde::Deserialize)]
+#[derive(Clone, PartialEq, Debug, serde::Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub(crate) struct Relocate {
     pub(crate) dimension: dimension::Kind,
     pub(crate) position: Coord3,
+
+    /// How far (in blocks) around the position to search for a safe landing spot if the position
+    /// itself is unsafe, if unset defaults to 16
+    #[serde(default)]
+    pub(crate) search_radius: Option<u32>,
+
+    /// What to do with the vehicle (and its other passengers) a relocated player is riding
+    #[serde(default)]
+    pub(crate) vehicles: Vehicles,
+
+    /// Whether to bring tamed pets left in regenerated chunks along with their relocated owner
+    #[serde(default)]
+    pub(crate) pets: bool,
+
+    /// Something to put in relocated players' inventories, so they know what happened
+    #[serde(default)]
+    pub(crate) note: Option<Note>,
+}
+
+#[derive(Clone, PartialEq, Debug, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum Note {
+    /// A written book, the text can refer to `{old-dimension}`, `{old-position}`,
+    /// `{new-dimension}` and `{new-position}`, and blank lines separate pages
+    #[serde(rename_all = "kebab-case")]
+    Book {
+        title: String,
+        author: String,
+        text: String,
+    },
+
+    /// A plain item
+    #[serde(rename_all = "kebab-case")]
+    Item { id: String },
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum Vehicles {
+    /// Move the vehicle and all its passengers along with the player
+    #[default]
+    Move,
+
+    /// Dismount the player, discarding the vehicle and its other passengers
+    Dismount,
 }
 
 #[derive(Clone, PartialEq, Debug, Default, serde::Deserialize)]
@@ -79,6 +136,129 @@ pub(crate) struct Entities {
     pub(crate) cull: bool,
 }
 
+#[derive(Clone, PartialEq, Debug, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct LostAndFound {
+    /// Dimension the lost and found chests are in
+    pub(crate) dimension: dimension::Kind,
+
+    /// Chests (or barrels) to fill with items recovered from deleted chunks, in order
+    pub(crate) chests: Vec<Coord3>,
+
+    /// File in the world directory to append a listing of every recovered container to
+    #[serde(default)]
+    pub(crate) report: Option<String>,
+}
+
+#[derive(Clone, PartialEq, Debug, Default, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct Seed {
+    /// How to pick the seed for each cycle with --randomize-seed
+    #[serde(default)]
+    pub(crate) schedule: Schedule,
+
+    /// Also write new seeds to `level-seed` when run on a server directory, so server.properties
+    /// matches the world
+    #[serde(default)]
+    pub(crate) sync_server_properties: bool,
+}
+
+#[serde_with::serde_as]
+#[derive(Clone, PartialEq, Debug, Default, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum Schedule {
+    /// A random seed that has not been used in a previous cycle
+    #[default]
+    Random,
+
+    /// The seeds from this list in order, one per cycle, as numbers or text
+    List(#[serde_as(as = "Vec<serde_with::TryFromInto<UnparsedSeed>>")] Vec<i64>),
+
+    /// Seeds derived from a base seed and the cycle number, so that the schedule is reproducible
+    /// without listing every seed
+    #[serde(rename_all = "kebab-case")]
+    Derived {
+        #[serde_as(as = "serde_with::TryFromInto<UnparsedSeed>")]
+        base: i64,
+    },
+}
+
+#[derive(Clone, PartialEq, Debug, serde::Deserialize)]
+#[serde(untagged)]
+enum UnparsedSeed {
+    Number(i64),
+    Text(String),
+}
+
+impl TryFrom<UnparsedSeed> for i64 {
+    type Error = Error;
+
+    #[culpa::throws]
+    fn try_from(seed: UnparsedSeed) -> Self {
+        match seed {
+            UnparsedSeed::Number(seed) => seed,
+            UnparsedSeed::Text(text) => parse_seed(&text)?,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug, Default, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct Level {
+    /// Game rules to set in level.dat, by their in-game name
+    #[serde(default)]
+    pub(crate) game_rules: BTreeMap<String, GameRule>,
+
+    /// Whether to reset the day time and total world time to 0
+    #[serde(default)]
+    pub(crate) reset_time: bool,
+
+    /// Whether to stop any rain or thunderstorm
+    #[serde(default)]
+    pub(crate) clear_weather: bool,
+
+    /// Where to put the world spawn, if unset and the current spawn is outside the persistent
+    /// areas of the overworld it will be moved to the ground at the center of the nearest one
+    #[serde(default)]
+    pub(crate) spawn: Option<Coord3>,
+}
+
+#[derive(Clone, PartialEq, Debug, serde::Deserialize)]
+#[serde(untagged)]
+pub(crate) enum GameRule {
+    Bool(bool),
+    Int(i64),
+}
+
+impl std::fmt::Display for GameRule {
+    #[culpa::throws(std::fmt::Error)]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) {
+        match self {
+            Self::Bool(value) => write!(f, "{value}")?,
+            Self::Int(value) => write!(f, "{value}")?,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug, Default, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct Maps {
+    /// What to do with unlocked maps that show regenerated chunks
+    #[serde(default)]
+    pub(crate) stale: StaleMaps,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum StaleMaps {
+    /// Blank the regenerated parts of the map, so they are redrawn when next explored
+    #[default]
+    Clear,
+
+    /// Keep a copy of the map in data/fc5-tool-archive, leaving the map itself as is
+    Archive,
+}
+
 #[serde_with::serde_as]
 #[derive(Clone, PartialEq, Debug, Default, serde::Deserialize)]
 #[serde(rename_all = "kebab-case")]
@@ -89,6 +269,37 @@ pub(crate) struct Dimension {
     pub(crate) persistent: Vec<PersistentArea>,
 }
 
+impl Dimension {
+    /// Regions containing at least one persistent chunk
+    pub(crate) fn kept_regions(&self) -> BTreeSet<Coord<i64>> {
+        BTreeSet::from_iter(self.persistent.iter().flat_map(
+            |PersistentArea::Square {
+                 top_left: tl,
+                 bottom_right: br,
+                 ..
+             }| {
+                let tlr = tl.chunk_to_region();
+                let brr = br.chunk_to_region();
+                ((tlr.x)..=(brr.x))
+                    .flat_map(move |x| ((tlr.z)..=(brr.z)).map(move |z| Coord { x, z }))
+            },
+        ))
+    }
+
+    /// Chunks inside any persistent area
+    pub(crate) fn kept_chunks(&self) -> BTreeSet<Coord<i64>> {
+        BTreeSet::from_iter(self.persistent.iter().flat_map(
+            |PersistentArea::Square {
+                 top_left: tl,
+                 bottom_right: br,
+                 ..
+             }| {
+                ((tl.x)..=(br.x)).flat_map(move |x| ((tl.z)..=(br.z)).map(move |z| Coord { x, z }))
+            },
+        ))
+    }
+}
+
 #[derive(Copy, Clone, PartialEq, Debug, Default, serde::Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub(crate) struct Blending {
@@ -142,6 +353,20 @@ impl PersistentArea {
             }
         }
     }
+
+    /// The chunk at the center of the area, rounding towards the top-left
+    pub(crate) fn center(&self) -> Coord<i64> {
+        match self {
+            Self::Square {
+                top_left,
+                bottom_right,
+                ..
+            } => Coord {
+                x: top_left.x + (bottom_right.x - top_left.x) / 2,
+                z: top_left.z + (bottom_right.z - top_left.z) / 2,
+            },
+        }
+    }
 }
 
 impl From<PersistentArea> for UnvalidatedPersistentArea {
@@ -194,8 +419,9 @@ impl TryFrom<UnvalidatedPersistentArea> for PersistentArea {
 #[allow(clippy::unwrap_used)]
 mod tests {
     use super::{
-        dimension, Blending, Config, Coord, Coord3, Dimension, Entities, HashMap, OutOfBounds,
-        PersistentArea, Players, Relocate,
+        dimension, BTreeMap, Blending, Config, Coord, Coord3, Dimension, Entities, GameRule,
+        HashMap, Level, LostAndFound, Note, OutOfBounds, PersistentArea, Players, Relocate,
+        Schedule, Seed, Vehicles,
     };
     use eyre::Error;
     use indoc::indoc;
@@ -212,6 +438,13 @@ mod tests {
                     out_of_bounds: None
                 },
                 entities: Entities { cull: false },
+                lost_and_found: None,
+                seed: Seed {
+                    schedule: Schedule::Random,
+                    sync_server_properties: false,
+                },
+                level: None,
+                maps: None,
                 dimension: HashMap::new(),
             }
         );
@@ -231,6 +464,13 @@ mod tests {
                     }),
                 },
                 entities: Entities { cull: false },
+                lost_and_found: None,
+                seed: Seed {
+                    schedule: Schedule::Random,
+                    sync_server_properties: false,
+                },
+                level: None,
+                maps: None,
                 dimension: HashMap::new(),
             }
         );
@@ -248,6 +488,13 @@ mod tests {
                     }),
                 },
                 entities: Entities { cull: false },
+                lost_and_found: None,
+                seed: Seed {
+                    schedule: Schedule::Random,
+                    sync_server_properties: false,
+                },
+                level: None,
+                maps: None,
                 dimension: HashMap::new(),
             }
         );
@@ -257,10 +504,35 @@ mod tests {
                 [players.out-of-bounds.relocate]
                 dimension = "overworld"
                 position = { x = -20.5, y = 70, z = 21.5 }
+                search-radius = 8
+                vehicles = "dismount"
+                pets = true
+                note.book.title = "World reset"
+                note.book.author = "FerrisCraft"
+                note.book.text = "You were at {old-position}.\n\nNow you are at {new-position}."
 
                 [entities]
                 cull = true
 
+                [lost-and-found]
+                dimension = "overworld"
+                chests = [{ x = 2, y = 64, z = -3 }]
+                report = "lost-and-found.txt"
+
+                [seed]
+                sync-server-properties = true
+
+                [seed.schedule.derived]
+                base = "hello"
+
+                [level]
+                reset-time = true
+                clear-weather = true
+                game-rules = { doDaylightCycle = false, spawnRadius = 0 }
+
+                [maps]
+                stale = "archive"
+
                 [[dimension.overworld.persistent]]
                 top-left = { x = -31, z = -31 }
                 bottom-right = { x = 31, z = 31 }
@@ -279,9 +551,43 @@ mod tests {
                             y: 70.0,
                             z: 21.5
                         },
+                        search_radius: Some(8),
+                        vehicles: Vehicles::Dismount,
+                        pets: true,
+                        note: Some(Note::Book {
+                            title: "World reset".into(),
+                            author: "FerrisCraft".into(),
+                            text: "You were at {old-position}.\n\nNow you are at {new-position}."
+                                .into(),
+                        }),
                     })),
                 },
                 entities: Entities { cull: true },
+                lost_and_found: Some(LostAndFound {
+                    dimension: dimension::Kind::Overworld,
+                    chests: vec![Coord3 {
+                        x: 2.0,
+                        y: 64.0,
+                        z: -3.0
+                    }],
+                    report: Some("lost-and-found.txt".into()),
+                }),
+                seed: Seed {
+                    schedule: Schedule::Derived { base: 99_162_322 },
+                    sync_server_properties: true,
+                },
+                level: Some(Level {
+                    game_rules: BTreeMap::from_iter([
+                        ("doDaylightCycle".into(), GameRule::Bool(false)),
+                        ("spawnRadius".into(), GameRule::Int(0)),
+                    ]),
+                    reset_time: true,
+                    clear_weather: true,
+                    spawn: None,
+                }),
+                maps: Some(Maps {
+                    stale: StaleMaps::Archive,
+                }),
                 dimension: HashMap::from_iter([(
                     dimension::Kind::Overworld,
                     Dimension {
@@ -303,6 +609,34 @@ mod tests {
         );
     }
 
+    #[test]
+    #[culpa::throws]
+    fn seed_schedule() {
+        assert_eq!(
+            Config::from_str(indoc! { r#"
+                [seed]
+                schedule.list = [5, "hello", "-7"]
+            "# })?
+            .seed,
+            Seed {
+                schedule: Schedule::List(vec![5, 99_162_322, -7]),
+                sync_server_properties: false,
+            }
+        );
+
+        assert_eq!(
+            Config::from_str(indoc! { r#"
+                [seed]
+                schedule = "random"
+            "# })?
+            .seed,
+            Seed {
+                schedule: Schedule::Random,
+                sync_server_properties: false,
+            }
+        );
+    }
+
     #[test]
     #[culpa::throws]
     fn bad_persistent_area_coordinates() {
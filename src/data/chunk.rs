@@ -1,7 +1,9 @@
-use eyre::{bail, ensure, Context, Error};
+use eyre::{bail, ensure, Context, ContextCompat, Error};
 use itertools::Itertools;
+use std::collections::HashSet;
+use uuid::Uuid;
 
-use super::{Compound, Coord};
+use super::{relocate_entity, uuid_from_nbt, Compound, Coord, Coord3};
 
 #[derive(Debug)]
 pub(crate) struct Chunk {
@@ -44,7 +46,18 @@ fn calculate_blending_heights(heightmap: [[i16; 16]; 16], offset: f64) -> [f64;
     })
 }
 
-#[derive(Copy, Clone, Debug)]
+/// A block entity holding items
+#[derive(Debug)]
+pub(crate) struct Container {
+    pub(crate) id: String,
+    pub(crate) x: i32,
+    pub(crate) y: i32,
+    pub(crate) z: i32,
+    pub(crate) items: Vec<Compound>,
+}
+
+#[derive(Copy, Clone, Debug, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
 pub(crate) enum Direction {
     North,
     East,
@@ -73,6 +86,33 @@ impl Chunk {
         fastnbt::to_bytes(&self.data)?
     }
 
+    /// The position the chunk's data says it is at, terrain chunks store it in `xPos` and `zPos`
+    /// (inside `Level` before 1.18) and entity chunks in `Position`, poi chunks don't store one
+    pub(super) fn stored_position(&self) -> Option<Coord<i64>> {
+        let from_compound = |compound: &Compound| match (compound.get("xPos"), compound.get("zPos"))
+        {
+            (Some(&fastnbt::Value::Int(x)), Some(&fastnbt::Value::Int(z))) => Some(Coord {
+                x: i64::from(x),
+                z: i64::from(z),
+            }),
+            _ => None,
+        };
+        if let Some(position) = from_compound(&self.data) {
+            return Some(position);
+        }
+        match (self.data.get("Level"), self.data.get("Position")) {
+            (Some(fastnbt::Value::Compound(level)), _) => from_compound(level),
+            (_, Some(fastnbt::Value::IntArray(position))) => match position[..] {
+                [x, z] => Some(Coord {
+                    x: i64::from(x),
+                    z: i64::from(z),
+                }),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
     #[culpa::throws]
     #[tracing::instrument(skip(self), fields(chunk.absolute_coord = %self.absolute_coord))]
     pub(crate) fn force_blending(&mut self) {
@@ -135,6 +175,219 @@ impl Chunk {
         };
         Heightmaps { chunk: self, data }
     }
+
+    /// Looks up the name of the block state at the given position, with `x` and `z` relative to
+    /// the chunk and `y` absolute, returns `None` if there is no section at that height
+    #[culpa::throws]
+    #[tracing::instrument(skip(self), fields(chunk.absolute_coord = %self.absolute_coord))]
+    pub(crate) fn block(&self, x: usize, y: i64, z: usize) -> Option<&str> {
+        ensure!(x < 16 && z < 16, "block is outside chunk");
+        let Some(fastnbt::Value::List(sections)) = self.data.get("sections") else {
+            bail!("bad sections")
+        };
+        for section in sections {
+            let fastnbt::Value::Compound(section) = section else {
+                bail!("bad section")
+            };
+            let Some(&fastnbt::Value::Byte(section_y)) = section.get("Y") else {
+                bail!("bad Y")
+            };
+            if i64::from(section_y) != y >> 4 {
+                continue;
+            }
+            let Some(fastnbt::Value::Compound(block_states)) = section.get("block_states") else {
+                // sections just outside the build height only carry light data
+                return None;
+            };
+            let Some(fastnbt::Value::List(palette)) = block_states.get("palette") else {
+                bail!("bad palette")
+            };
+            let index = if palette.len() == 1 {
+                0
+            } else {
+                let Some(fastnbt::Value::LongArray(data)) = block_states.get("data") else {
+                    bail!("bad data")
+                };
+                // entries are packed without spanning longs, using at least 4 bits each
+                let bits = (usize::BITS - palette.len().saturating_sub(1).leading_zeros()).max(4);
+                let per_long = usize::try_from(64 / bits)?;
+                let offset = usize::try_from(y.rem_euclid(16))? * 256 + z * 16 + x;
+                let &long = data
+                    .get(offset / per_long)
+                    .context("not enough values in block_states")?;
+                let shift = u32::try_from(offset % per_long)? * bits;
+                usize::try_from((0u64.wrapping_add_signed(long) >> shift) & ((1 << bits) - 1))?
+            };
+            let Some(fastnbt::Value::Compound(state)) = palette.get(index) else {
+                bail!("bad palette entry")
+            };
+            let Some(fastnbt::Value::String(name)) = state.get("Name") else {
+                bail!("bad Name")
+            };
+            return Some(name.as_str());
+        }
+        None
+    }
+
+    /// Finds all block entities in this chunk that hold items
+    #[culpa::throws]
+    #[tracing::instrument(skip(self), fields(chunk.absolute_coord = %self.absolute_coord))]
+    pub(crate) fn containers(&self) -> Vec<Container> {
+        let Some(fastnbt::Value::List(block_entities)) = self.data.get("block_entities") else {
+            bail!("bad block_entities")
+        };
+        let mut containers = Vec::new();
+        for block_entity in block_entities {
+            let fastnbt::Value::Compound(block_entity) = block_entity else {
+                bail!("bad block entity")
+            };
+            let Some(items) = block_entity.get("Items") else {
+                continue;
+            };
+            let (
+                Some(fastnbt::Value::String(id)),
+                Some(&fastnbt::Value::Int(x)),
+                Some(&fastnbt::Value::Int(y)),
+                Some(&fastnbt::Value::Int(z)),
+                fastnbt::Value::List(items),
+            ) = (
+                block_entity.get("id"),
+                block_entity.get("x"),
+                block_entity.get("y"),
+                block_entity.get("z"),
+                items,
+            )
+            else {
+                bail!("bad container")
+            };
+            let items = Result::<Vec<_>, Error>::from_iter(items.iter().map(|item| {
+                let fastnbt::Value::Compound(item) = item else {
+                    bail!("bad item")
+                };
+                let mut item = item.clone();
+                item.remove("Slot");
+                Ok(item)
+            }))?;
+            containers.push(Container {
+                id: id.clone(),
+                x,
+                y,
+                z,
+                items,
+            });
+        }
+        containers
+    }
+
+    /// Removes the items from every block entity in this chunk that holds them
+    #[culpa::throws]
+    #[tracing::instrument(skip(self), fields(chunk.absolute_coord = %self.absolute_coord))]
+    pub(crate) fn empty_containers(&mut self) {
+        let Some(fastnbt::Value::List(block_entities)) = self.data.get_mut("block_entities") else {
+            bail!("bad block_entities")
+        };
+        for block_entity in block_entities {
+            let fastnbt::Value::Compound(block_entity) = block_entity else {
+                bail!("bad block entity")
+            };
+            if let Some(items) = block_entity.get_mut("Items") {
+                *items = fastnbt::Value::List(Vec::new());
+            }
+        }
+    }
+
+    /// Puts items into the free slots of the chest or barrel at the given block, returning any
+    /// that did not fit
+    #[culpa::throws]
+    #[tracing::instrument(skip(self, items), fields(chunk.absolute_coord = %self.absolute_coord, block.coord = %coord, block.y = y))]
+    pub(crate) fn fill_container(
+        &mut self,
+        coord: Coord<i64>,
+        y: i64,
+        items: Vec<Compound>,
+    ) -> Vec<Compound> {
+        const SLOTS: i8 = 27;
+
+        let Some(fastnbt::Value::List(block_entities)) = self.data.get_mut("block_entities") else {
+            bail!("bad block_entities")
+        };
+        let container = block_entities
+            .iter_mut()
+            .find_map(|block_entity| {
+                let fastnbt::Value::Compound(block_entity) = block_entity else {
+                    return None;
+                };
+                let position = (
+                    block_entity.get("x").and_then(fastnbt::Value::as_i64),
+                    block_entity.get("y").and_then(fastnbt::Value::as_i64),
+                    block_entity.get("z").and_then(fastnbt::Value::as_i64),
+                );
+                (position == (Some(coord.x), Some(y), Some(coord.z))).then_some(block_entity)
+            })
+            .context("missing container")?;
+        let Some(fastnbt::Value::String(id)) = container.get("id") else {
+            bail!("bad container id")
+        };
+        ensure!(
+            matches!(
+                id.as_str(),
+                "minecraft:chest" | "minecraft:trapped_chest" | "minecraft:barrel"
+            ),
+            "unsupported container {id}"
+        );
+        let fastnbt::Value::List(contents) = container
+            .entry("Items".into())
+            .or_insert_with(|| fastnbt::Value::List(Vec::new()))
+        else {
+            bail!("bad Items")
+        };
+        let used = HashSet::<i64>::from_iter(contents.iter().filter_map(|item| match item {
+            fastnbt::Value::Compound(item) => item.get("Slot").and_then(fastnbt::Value::as_i64),
+            _ => None,
+        }));
+        let free = (0..SLOTS).filter(|&slot| !used.contains(&i64::from(slot)));
+        let mut items = items.into_iter();
+        for (slot, mut item) in free.zip(items.by_ref()) {
+            item.insert("Slot".into(), fastnbt::Value::Byte(slot));
+            contents.push(fastnbt::Value::Compound(item));
+        }
+        Vec::from_iter(items)
+    }
+
+    /// Removes and returns all entities in this entity chunk owned by one of the given players
+    #[culpa::throws]
+    #[tracing::instrument(skip_all, fields(chunk.absolute_coord = %self.absolute_coord))]
+    pub(crate) fn take_owned_entities(&mut self, owners: &HashSet<Uuid>) -> Vec<Compound> {
+        let Some(fastnbt::Value::List(entities)) = self.data.get_mut("Entities") else {
+            bail!("bad Entities")
+        };
+        let mut owned = Vec::new();
+        let mut kept = Vec::new();
+        for entity in std::mem::take(entities) {
+            let fastnbt::Value::Compound(entity) = entity else {
+                bail!("bad entity")
+            };
+            match entity.get("Owner").map(uuid_from_nbt).transpose()? {
+                Some(owner) if owners.contains(&owner) => owned.push(entity),
+                _ => kept.push(fastnbt::Value::Compound(entity)),
+            }
+        }
+        *entities = kept;
+        owned
+    }
+
+    /// Adds entities to this entity chunk, moving them to the given position
+    #[culpa::throws]
+    #[tracing::instrument(skip(self, entities), fields(chunk.absolute_coord = %self.absolute_coord))]
+    pub(crate) fn add_entities(&mut self, entities: Vec<Compound>, position: Coord3) {
+        let Some(fastnbt::Value::List(list)) = self.data.get_mut("Entities") else {
+            bail!("bad Entities")
+        };
+        for mut entity in entities {
+            relocate_entity(&mut entity, position)?;
+            list.push(fastnbt::Value::Compound(entity));
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -179,3 +432,96 @@ impl Heightmaps<'_> {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Chunk;
+    use crate::data::{Compound, Coord};
+    use eyre::Error;
+    use pretty_assertions::assert_eq;
+
+    /// A chunk with a single section at `section_y` holding the given block states
+    fn chunk(section_y: i8, palette: &[String], data: Option<Vec<i64>>) -> Chunk {
+        let palette = Vec::from_iter(palette.iter().map(|name| {
+            fastnbt::Value::Compound(Compound::from_iter([(
+                "Name".into(),
+                fastnbt::Value::String(name.clone()),
+            )]))
+        }));
+        let mut block_states =
+            Compound::from_iter([("palette".into(), fastnbt::Value::List(palette))]);
+        if let Some(data) = data {
+            block_states.insert(
+                "data".into(),
+                fastnbt::Value::LongArray(fastnbt::LongArray::new(data)),
+            );
+        }
+        let section = Compound::from_iter([
+            ("Y".into(), fastnbt::Value::Byte(section_y)),
+            (
+                "block_states".into(),
+                fastnbt::Value::Compound(block_states),
+            ),
+        ]);
+        Chunk {
+            relative_coord: Coord { x: 0, z: 0 },
+            absolute_coord: Coord { x: 0, z: 0 },
+            data: Compound::from_iter([(
+                "sections".into(),
+                fastnbt::Value::List(vec![fastnbt::Value::Compound(section)]),
+            )]),
+        }
+    }
+
+    fn palette(len: usize) -> Vec<String> {
+        Vec::from_iter((0..len).map(|i| format!("minecraft:block_{i}")))
+    }
+
+    /// Longs as stored in NBT, which only has signed ones
+    fn signed(longs: Vec<u64>) -> Vec<i64> {
+        Vec::from_iter(
+            longs
+                .into_iter()
+                .map(|long| i64::from_ne_bytes(long.to_ne_bytes())),
+        )
+    }
+
+    #[test]
+    #[culpa::throws]
+    fn single_entry_palette() {
+        let chunk = chunk(0, &palette(1), None);
+        assert_eq!(chunk.block(3, 7, 9)?, Some("minecraft:block_0"));
+        assert_eq!(chunk.block(3, 16, 9)?, None);
+    }
+
+    #[test]
+    #[culpa::throws]
+    fn four_bits_per_entry() {
+        // two entries still use the minimum of four bits, sixteen to a long
+        let mut data = vec![0; 256];
+        // the last block of the section, y 15 z 15 x 15
+        data[255] = 1 << 60;
+        // x 1 of the first row
+        data[0] = 1 << 4;
+        let chunk = chunk(-1, &palette(2), Some(signed(data)));
+        assert_eq!(chunk.block(15, -1, 15)?, Some("minecraft:block_1"));
+        assert_eq!(chunk.block(14, -1, 15)?, Some("minecraft:block_0"));
+        assert_eq!(chunk.block(1, -16, 0)?, Some("minecraft:block_1"));
+        assert_eq!(chunk.block(0, -16, 0)?, Some("minecraft:block_0"));
+    }
+
+    #[test]
+    #[culpa::throws]
+    fn five_bits_per_entry_dont_cross_longs() {
+        // twelve entries fit in a long, the top four bits are padding and the thirteenth entry
+        // starts the next long rather than spanning both
+        let mut data = vec![0; 4096_usize.div_ceil(12)];
+        data[0] = (2 << 55) | (0xf << 60);
+        data[1] = 3;
+        let chunk = chunk(0, &palette(17), Some(signed(data)));
+        assert_eq!(chunk.block(0, 0, 0)?, Some("minecraft:block_0"));
+        assert_eq!(chunk.block(11, 0, 0)?, Some("minecraft:block_2"));
+        assert_eq!(chunk.block(12, 0, 0)?, Some("minecraft:block_3"));
+        assert_eq!(chunk.block(13, 0, 0)?, Some("minecraft:block_0"));
+    }
+}
@@ -0,0 +1,237 @@
+use eyre::{bail, ensure, Context, Error};
+use std::io::{Read, Write};
+
+/// How a chunk is compressed inside a region file
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub(crate) enum Compression {
+    Gzip,
+    Zlib,
+    Uncompressed,
+    /// lz4-java's block stream format, selectable with `region-file-compression=lz4` since 1.20.5
+    Lz4,
+}
+
+impl std::fmt::Display for Compression {
+    #[culpa::throws(std::fmt::Error)]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) {
+        match self {
+            Compression::Gzip => f.write_str("gzip")?,
+            Compression::Zlib => f.write_str("zlib")?,
+            Compression::Uncompressed => f.write_str("none")?,
+            Compression::Lz4 => f.write_str("lz4")?,
+        }
+    }
+}
+
+const LZ4_MAGIC: &[u8; 8] = b"LZ4Block";
+const LZ4_HEADER_LEN: usize = LZ4_MAGIC.len() + 1 + 4 + 4 + 4;
+const LZ4_BLOCK_SIZE: usize = 1 << 16;
+const LZ4_METHOD_RAW: u8 = 0x10;
+const LZ4_METHOD_LZ4: u8 = 0x20;
+/// log2 of the block size minus 10, stored in the low bits of each block's token
+const LZ4_COMPRESSION_LEVEL: u8 = 6;
+const LZ4_CHECKSUM_SEED: u32 = 0x9747_b28c;
+
+/// lz4-java only keeps the low 28 bits of the xxhash
+fn lz4_checksum(data: &[u8]) -> u32 {
+    xxhash_rust::xxh32::xxh32(data, LZ4_CHECKSUM_SEED) & 0x0fff_ffff
+}
+
+fn lz4_block(method: u8, compressed: &[u8], original_len: usize, checksum: u32) -> Vec<u8> {
+    let mut block = Vec::with_capacity(LZ4_HEADER_LEN + compressed.len());
+    block.extend_from_slice(LZ4_MAGIC);
+    block.push(method | LZ4_COMPRESSION_LEVEL);
+    for int in [compressed.len(), original_len] {
+        let int = u32::try_from(int).expect("blocks are smaller than LZ4_BLOCK_SIZE");
+        block.extend_from_slice(&int.to_le_bytes());
+    }
+    block.extend_from_slice(&checksum.to_le_bytes());
+    block.extend_from_slice(compressed);
+    block
+}
+
+fn lz4_compress(data: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(data.len() / 2);
+    for original in data.chunks(LZ4_BLOCK_SIZE) {
+        let checksum = lz4_checksum(original);
+        let compressed = lz4_flex::block::compress(original);
+        // like lz4-java, store blocks that don't shrink as-is
+        if compressed.len() < original.len() {
+            result.extend(lz4_block(
+                LZ4_METHOD_LZ4,
+                &compressed,
+                original.len(),
+                checksum,
+            ));
+        } else {
+            result.extend(lz4_block(
+                LZ4_METHOD_RAW,
+                original,
+                original.len(),
+                checksum,
+            ));
+        }
+    }
+    // an empty raw block marks the end of the stream
+    result.extend(lz4_block(LZ4_METHOD_RAW, &[], 0, 0));
+    result
+}
+
+#[culpa::throws]
+fn lz4_decompress(mut data: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(data.len() * 2);
+    loop {
+        ensure!(data.len() >= LZ4_HEADER_LEN, "truncated lz4 block header");
+        let (header, rest) = data.split_at(LZ4_HEADER_LEN);
+        let (magic, header) = header.split_at(LZ4_MAGIC.len());
+        ensure!(magic == LZ4_MAGIC, "bad lz4 block magic");
+        let [token, c0, c1, c2, c3, o0, o1, o2, o3, s0, s1, s2, s3] = header[..] else {
+            unreachable!("header is LZ4_HEADER_LEN long")
+        };
+        let compressed_len = usize::try_from(u32::from_le_bytes([c0, c1, c2, c3]))?;
+        let original_len = usize::try_from(u32::from_le_bytes([o0, o1, o2, o3]))?;
+        let checksum = u32::from_le_bytes([s0, s1, s2, s3]);
+
+        if original_len == 0 {
+            ensure!(
+                compressed_len == 0 && checksum == 0,
+                "bad lz4 end of stream"
+            );
+            break;
+        }
+
+        ensure!(rest.len() >= compressed_len, "truncated lz4 block");
+        let (compressed, rest) = rest.split_at(compressed_len);
+        let start = result.len();
+        match token & 0xf0 {
+            LZ4_METHOD_RAW => {
+                ensure!(compressed_len == original_len, "bad raw lz4 block length");
+                result.extend_from_slice(compressed);
+            }
+            LZ4_METHOD_LZ4 => {
+                result.resize(start + original_len, 0);
+                let len = lz4_flex::block::decompress_into(compressed, &mut result[start..])
+                    .context("decompressing lz4 block")?;
+                ensure!(len == original_len, "bad lz4 block length");
+            }
+            method => bail!("unknown lz4 block method {method:#x}"),
+        }
+        ensure!(
+            lz4_checksum(&result[start..]) == checksum,
+            "bad lz4 block checksum"
+        );
+
+        data = rest;
+    }
+    result
+}
+
+impl Compression {
+    /// The compression type byte used in region files
+    pub(super) fn id(self) -> u8 {
+        match self {
+            Compression::Gzip => 1,
+            Compression::Zlib => 2,
+            Compression::Uncompressed => 3,
+            Compression::Lz4 => 4,
+        }
+    }
+
+    #[culpa::throws]
+    pub(super) fn from_id(id: u8) -> Self {
+        match id {
+            1 => Compression::Gzip,
+            2 => Compression::Zlib,
+            3 => Compression::Uncompressed,
+            4 => Compression::Lz4,
+            other => bail!("unknown chunk compression {other}"),
+        }
+    }
+
+    #[culpa::throws]
+    #[tracing::instrument(level = "trace", skip(data), fields(data.len = data.len()))]
+    pub(super) fn compress(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Compression::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()?
+            }
+            Compression::Zlib => {
+                let mut encoder =
+                    flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()?
+            }
+            Compression::Uncompressed => data.to_owned(),
+            Compression::Lz4 => lz4_compress(data),
+        }
+    }
+
+    #[culpa::throws]
+    #[tracing::instrument(level = "trace", skip(data), fields(data.len = data.len()))]
+    pub(super) fn decompress(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Compression::Gzip => {
+                let mut result = Vec::with_capacity(data.len() * 4);
+                flate2::read::GzDecoder::new(data).read_to_end(&mut result)?;
+                result
+            }
+            Compression::Zlib => {
+                let mut result = Vec::with_capacity(data.len() * 4);
+                flate2::read::ZlibDecoder::new(data).read_to_end(&mut result)?;
+                result
+            }
+            Compression::Uncompressed => data.to_owned(),
+            Compression::Lz4 => lz4_decompress(data)?,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Compression;
+    use eyre::Error;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    #[culpa::throws]
+    fn round_trip() {
+        let data = Vec::from_iter((0..200_000_u32).flat_map(|i| (i / 7).to_be_bytes()));
+        for compression in [
+            Compression::Gzip,
+            Compression::Zlib,
+            Compression::Uncompressed,
+            Compression::Lz4,
+        ] {
+            let compressed = compression.compress(&data)?;
+            assert_eq!(compression.decompress(&compressed)?, data, "{compression}");
+        }
+    }
+
+    #[test]
+    #[culpa::throws]
+    fn lz4_block_stream() {
+        // the layout lz4-java's LZ4BlockOutputStream uses for input too short to compress
+        let compressed = [
+            b"LZ4Block".as_slice(),
+            &[0x16, 5, 0, 0, 0, 5, 0, 0, 0],
+            &super::lz4_checksum(b"hello").to_le_bytes(),
+            b"hello",
+            b"LZ4Block",
+            &[0x16, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+        ]
+        .concat();
+        assert_eq!(Compression::Lz4.decompress(&compressed)?, b"hello");
+        assert_eq!(Compression::Lz4.compress(b"hello")?, compressed);
+    }
+
+    #[test]
+    fn lz4_bad_checksum() {
+        let mut compressed = super::lz4_compress(b"hello");
+        // first byte of the checksum
+        compressed[17] ^= 1;
+        assert!(Compression::Lz4.decompress(&compressed).is_err());
+    }
+}
@@ -4,7 +4,9 @@ use std::{
     str::FromStr,
 };
 
-#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq, Ord, PartialOrd, serde::Deserialize)]
+#[derive(
+    Copy, Clone, Debug, Hash, Eq, PartialEq, Ord, PartialOrd, serde::Deserialize, serde::Serialize,
+)]
 pub(crate) struct Coord<T> {
     pub(crate) x: T,
     pub(crate) z: T,
@@ -1,6 +1,6 @@
 use super::Coord;
 
-#[derive(Copy, Clone, PartialEq, Debug, serde::Deserialize)]
+#[derive(Copy, Clone, PartialEq, Debug, serde::Deserialize, serde::Serialize)]
 pub(crate) struct Coord3 {
     pub(crate) x: f64,
     pub(crate) y: f64,
@@ -15,6 +15,33 @@ impl Coord3 {
             z: self.z as i64,
         }
     }
+
+    /// The horizontal coordinate of the block containing this position
+    pub(crate) fn to_block_coord(self) -> Coord<i64> {
+        #[allow(clippy::as_conversions)] // no alternative yet
+        Coord {
+            x: self.x.floor() as i64,
+            z: self.z.floor() as i64,
+        }
+    }
+
+    /// The vertical coordinate of the block containing this position
+    pub(crate) fn block_y(self) -> i64 {
+        #[allow(clippy::as_conversions)] // no alternative yet
+        {
+            self.y.floor() as i64
+        }
+    }
+
+    /// The position of an entity standing in the center of the given block
+    pub(crate) fn from_block(coord: Coord<i64>, y: i64) -> Self {
+        #[allow(clippy::as_conversions)] // no alternative yet
+        Self {
+            x: coord.x as f64 + 0.5,
+            y: y as f64,
+            z: coord.z as f64 + 0.5,
+        }
+    }
 }
 
 impl std::fmt::Display for Coord3 {
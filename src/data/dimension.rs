@@ -1,9 +1,12 @@
 use camino::{Utf8Path, Utf8PathBuf};
 use eyre::{bail, Context, ContextCompat, Error, Result};
+use std::collections::{BTreeMap, HashMap, VecDeque};
 
-use super::{Coord, Region};
+use super::{read_compound, remove_file_if_exists, write_compound, Compound, Coord, Region};
 
-#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, serde::Deserialize)]
+#[derive(
+    Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, serde::Deserialize, serde::Serialize,
+)]
 #[serde(rename_all = "kebab-case")]
 pub(crate) enum Kind {
     Overworld,
@@ -23,6 +26,8 @@ impl std::fmt::Display for Kind {
 }
 
 impl Kind {
+    pub(crate) const ALL: [Kind; 3] = [Kind::Overworld, Kind::Nether, Kind::End];
+
     pub(super) fn nbt(&self) -> fastnbt::Value {
         match self {
             Kind::Overworld => fastnbt::Value::String("minecraft:the_overworld".into()),
@@ -43,10 +48,158 @@ impl Kind {
     }
 }
 
+#[culpa::throws]
+fn region_from_entry(entry: std::io::Result<std::fs::DirEntry>) -> Option<Region> {
+    let path = Utf8PathBuf::try_from(entry.context("reading dir entry")?.path())?;
+    // oversized chunks and interrupted compactions leave other files alongside the regions
+    if path.extension() != Some("mca") {
+        return None;
+    }
+    Region::from_path(path.clone()).with_context(|| format!("opening {path}"))?
+}
+
+/// Lists the regions in a directory, a dimension that was never generated has none
+#[culpa::throws]
+fn regions_in(directory: &Utf8Path) -> impl Iterator<Item = Result<Region>> {
+    let entries = match std::fs::read_dir(directory) {
+        Ok(entries) => Some(entries),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => Err(e).with_context(|| format!("reading {directory}"))?,
+    };
+    entries
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| region_from_entry(entry).transpose())
+}
+
+/// Lists the coordinates of the regions in a directory from their file names, without opening them
+#[culpa::throws]
+fn region_coords_in(directory: &Utf8Path) -> impl Iterator<Item = Result<Coord<i64>>> {
+    let entries = match std::fs::read_dir(directory) {
+        Ok(entries) => Some(entries),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => Err(e).with_context(|| format!("reading {directory}"))?,
+    };
+    entries.into_iter().flatten().filter_map(|entry| {
+        (|| {
+            let path = Utf8PathBuf::try_from(entry.context("reading dir entry")?.path())?;
+            if path.extension() != Some("mca") {
+                return Ok(None);
+            }
+            Ok(Some(Coord::from_region_file(
+                path.file_name().context("missing filename")?,
+            )?))
+        })()
+        .transpose()
+    })
+}
+
+/// The size of a file, a missing file has none
+#[culpa::throws]
+pub(crate) fn file_len(path: &Utf8Path) -> u64 {
+    match std::fs::metadata(path) {
+        Ok(metadata) => metadata.len(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => 0,
+        Err(e) => Err(e).with_context(|| format!("reading metadata of {path}"))?,
+    }
+}
+
+/// Total size of the files directly inside a directory, zero if it doesn't exist
+#[culpa::throws]
+fn directory_len(directory: &Utf8Path) -> u64 {
+    let entries = match std::fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return 0,
+        Err(e) => Err(e).with_context(|| format!("reading {directory}"))?,
+    };
+    let mut len = 0;
+    for entry in entries {
+        let metadata = entry?.metadata()?;
+        if metadata.is_file() {
+            len += metadata.len();
+        }
+    }
+    len
+}
+
+/// The chunk an external `c.x.z.mcc` file holds, from its name
+fn external_chunk_coord(name: &str) -> Option<Coord<i64>> {
+    let mut it = name.split('.');
+    match (it.next(), it.next(), it.next(), it.next(), it.next()) {
+        (Some("c"), Some(x), Some(z), Some("mcc"), None) => Some(Coord {
+            x: x.parse().ok()?,
+            z: z.parse().ok()?,
+        }),
+        _ => None,
+    }
+}
+
+/// The external files of oversized chunks, by the region they belong to and then by their chunk
+pub(crate) type ExternalChunkFiles = BTreeMap<Coord<i64>, Vec<(Coord<i64>, Utf8PathBuf)>>;
+
+/// Lists the external files of oversized chunks in a directory of regions
+///
+/// They are found by name rather than through the regions' headers, so a region too damaged to
+/// open still has them found. The directory is read once for all its regions, there can be tens of
+/// thousands of them.
+#[culpa::throws]
+fn external_chunk_files_in(directory: &Utf8Path) -> ExternalChunkFiles {
+    let entries = match std::fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return BTreeMap::new(),
+        Err(e) => Err(e).with_context(|| format!("reading {directory}"))?,
+    };
+    let mut files = ExternalChunkFiles::new();
+    for entry in entries {
+        let path = Utf8PathBuf::try_from(entry.context("reading dir entry")?.path())?;
+        let Some(chunk_coord) = path.file_name().and_then(external_chunk_coord) else {
+            continue;
+        };
+        files
+            .entry(chunk_coord.chunk_to_region())
+            .or_default()
+            .push((chunk_coord, path));
+    }
+    files
+}
+
+/// Removes a region file along with the external files of its oversized chunks, returning how
+/// many bytes were freed
+///
+/// The region itself is never opened, so a truncated or corrupt one is removed all the same.
+#[culpa::throws]
+fn remove_region_file(path: &Utf8Path, external_files: &ExternalChunkFiles) -> u64 {
+    let mut freed = file_len(path)?;
+    remove_file_if_exists(path)?;
+    let region_coord = Coord::from_region_file(path.file_name().context("missing filename")?)?;
+    // an external file left behind only wastes space, it doesn't stop the region being deleted
+    for (_, external_path) in external_files.get(&region_coord).into_iter().flatten() {
+        let len = file_len(external_path).unwrap_or_default();
+        match remove_file_if_exists(external_path) {
+            Ok(()) => {
+                freed += len;
+                tracing::debug!(%external_path, "Deleted external chunk");
+            }
+            Err(error) => {
+                tracing::warn!(%external_path, "Could not delete external chunk: {error:#}");
+            }
+        }
+    }
+    freed
+}
+
+/// How many regions a dimension keeps open at once, well under the usual open file limit
+const REGION_CACHE_SIZE: usize = 64;
+
 #[derive(Debug)]
 pub(crate) struct Dimension {
     pub(crate) kind: Kind,
     pub(crate) directory: Utf8PathBuf,
+    /// Regions opened by [`Dimension::cached_region`], including ones that don't exist, written
+    /// back when evicted or dropped
+    region_cache: HashMap<Coord<i64>, Option<Region>>,
+    /// Cached region coordinates, oldest first
+    region_cache_order: VecDeque<Coord<i64>>,
 }
 
 impl Dimension {
@@ -58,84 +211,209 @@ impl Dimension {
                 Kind::Nether => relative_to.join("DIM-1"),
                 Kind::End => relative_to.join("DIM1"),
             },
+            region_cache: HashMap::new(),
+            region_cache_order: VecDeque::new(),
         }
     }
 
+    pub(crate) fn region_path(&self, coord: Coord<i64>) -> Utf8PathBuf {
+        let Coord { x, z } = coord;
+        self.directory.join("region").join(format!("r.{x}.{z}.mca"))
+    }
+
     #[culpa::throws]
     #[tracing::instrument(skip_all, fields(dimension.kind = %self.kind, dimension.directory = %self.directory, region.coord = %coord))]
     pub(crate) fn region(&self, coord: Coord<i64>) -> Option<Region> {
-        let Coord { x, z } = coord;
-        let path = self.directory.join("region").join(format!("r.{x}.{z}.mca"));
-        Region::from_path(path)?
+        let path = self.region_path(coord);
+        Region::from_path(path.clone()).with_context(|| format!("opening {path}"))?
     }
 
     #[culpa::throws]
     #[tracing::instrument(skip_all, fields(dimension.kind = %self.kind, dimension.directory = %self.directory))]
     pub(crate) fn regions(&self) -> impl Iterator<Item = Result<Region>> {
-        std::fs::read_dir(self.directory.join("region"))
-            .context("reading region dir")?
-            .filter_map(|entry| {
-                entry
-                    .context("reading dir entry")
-                    .and_then(|entry| Ok(Region::from_path(entry.path().try_into()?)?))
-                    .transpose()
-            })
+        regions_in(&self.directory.join("region"))?
+    }
+
+    /// The coordinates of every region, without opening them
+    #[culpa::throws]
+    #[tracing::instrument(skip_all, fields(dimension.kind = %self.kind, dimension.directory = %self.directory))]
+    pub(crate) fn region_coords(&self) -> impl Iterator<Item = Result<Coord<i64>>> {
+        region_coords_in(&self.directory.join("region"))?
     }
 
     #[culpa::throws]
     #[tracing::instrument(skip_all, fields(dimension.kind = %self.kind, dimension.directory = %self.directory, region.coord = %coord))]
-    pub(crate) fn remove_region(&self, coord: Coord<i64>) {
-        let Coord { x, z } = coord;
-        let path = self.directory.join("region").join(format!("r.{x}.{z}.mca"));
-        match std::fs::remove_file(path) {
-            Ok(()) => Ok(()),
-            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
-            res => res,
-        }?;
+    pub(crate) fn remove_region(
+        &self,
+        coord: Coord<i64>,
+        external_files: &ExternalChunkFiles,
+    ) -> u64 {
+        remove_region_file(&self.region_path(coord), external_files)?
     }
 
+    /// The external files of oversized chunks beside the regions, see [`ExternalChunkFiles`]
     #[culpa::throws]
-    #[tracing::instrument(skip_all, fields(dimension.kind = %self.kind, dimension.directory = %self.directory, chunk.absolute_coord = %absolute_coord))]
-    pub(crate) fn region_for_chunk(&self, absolute_coord: Coord<i64>) -> Option<Region> {
-        self.region(absolute_coord.chunk_to_region())?
+    #[tracing::instrument(skip_all, fields(dimension.kind = %self.kind, dimension.directory = %self.directory))]
+    pub(crate) fn external_chunk_files(&self) -> ExternalChunkFiles {
+        external_chunk_files_in(&self.directory.join("region"))?
     }
 
+    /// Like [`Dimension::region`], but keeps the region open so later calls for it don't reopen
+    /// the file, changes to its header are written back when it is evicted or the dimension is
+    /// dropped
     #[culpa::throws]
     #[tracing::instrument(skip_all, fields(dimension.kind = %self.kind, dimension.directory = %self.directory, region.coord = %coord))]
-    pub(crate) fn entity_region(&self, coord: Coord<i64>) -> Option<Region> {
+    pub(crate) fn cached_region(&mut self, coord: Coord<i64>) -> Option<&mut Region> {
+        if !self.region_cache.contains_key(&coord) {
+            let region = self.region(coord)?;
+            while self.region_cache_order.len() >= REGION_CACHE_SIZE {
+                let evicted = self
+                    .region_cache_order
+                    .pop_front()
+                    .expect("cache is not empty");
+                if let Some(Some(mut region)) = self.region_cache.remove(&evicted) {
+                    region.flush()?;
+                }
+            }
+            self.region_cache.insert(coord, region);
+            self.region_cache_order.push_back(coord);
+        }
+        self.region_cache
+            .get_mut(&coord)
+            .expect("region was just cached")
+            .as_mut()
+    }
+
+    #[culpa::throws]
+    #[tracing::instrument(skip_all, fields(dimension.kind = %self.kind, dimension.directory = %self.directory, chunk.absolute_coord = %absolute_coord))]
+    pub(crate) fn cached_region_for_chunk(
+        &mut self,
+        absolute_coord: Coord<i64>,
+    ) -> Option<&mut Region> {
+        self.cached_region(absolute_coord.chunk_to_region())?
+    }
+
+    /// Writes back every cached region, so errors can be reported rather than only logged on drop
+    #[culpa::throws]
+    #[tracing::instrument(skip_all, fields(dimension.kind = %self.kind, dimension.directory = %self.directory))]
+    pub(crate) fn flush(&mut self) {
+        for region in self.region_cache.values_mut().flatten() {
+            region.flush()?;
+        }
+    }
+
+    pub(crate) fn entity_region_path(&self, coord: Coord<i64>) -> Utf8PathBuf {
         let Coord { x, z } = coord;
-        let path = self
-            .directory
+        self.directory
             .join("entities")
-            .join(format!("r.{x}.{z}.mca"));
-        Region::from_path(path)?
+            .join(format!("r.{x}.{z}.mca"))
+    }
+
+    #[culpa::throws]
+    #[tracing::instrument(skip_all, fields(dimension.kind = %self.kind, dimension.directory = %self.directory, region.coord = %coord))]
+    pub(crate) fn entity_region(&self, coord: Coord<i64>) -> Option<Region> {
+        let path = self.entity_region_path(coord);
+        Region::from_path(path.clone()).with_context(|| format!("opening {path}"))?
+    }
+
+    /// Opens the entity region, creating an empty one if it does not exist yet
+    #[culpa::throws]
+    #[tracing::instrument(skip_all, fields(dimension.kind = %self.kind, dimension.directory = %self.directory, region.coord = %coord))]
+    pub(crate) fn entity_region_or_create(&self, coord: Coord<i64>) -> Region {
+        let path = self.entity_region_path(coord);
+        match Region::from_path(path.clone())? {
+            Some(region) => region,
+            None => Region::create(path)?,
+        }
     }
 
     #[culpa::throws]
     #[tracing::instrument(skip_all, fields(dimension.kind = %self.kind, dimension.directory = %self.directory))]
     pub(crate) fn entity_regions(&self) -> impl Iterator<Item = Result<Region>> {
-        std::fs::read_dir(self.directory.join("entities"))
-            .context("reading entities dir")?
-            .filter_map(|entry| {
-                entry
-                    .context("reading dir entry")
-                    .and_then(|entry| Ok(Region::from_path(entry.path().try_into()?)?))
-                    .transpose()
-            })
+        regions_in(&self.directory.join("entities"))?
+    }
+
+    /// The coordinates of every entity region, without opening them
+    #[culpa::throws]
+    #[tracing::instrument(skip_all, fields(dimension.kind = %self.kind, dimension.directory = %self.directory))]
+    pub(crate) fn entity_region_coords(&self) -> impl Iterator<Item = Result<Coord<i64>>> {
+        region_coords_in(&self.directory.join("entities"))?
+    }
+
+    /// Total size of the files in one of the dimension's directories, such as `region`
+    #[culpa::throws]
+    #[tracing::instrument(skip_all, fields(dimension.kind = %self.kind, dimension.directory = %self.directory))]
+    pub(crate) fn directory_len(&self, name: &str) -> u64 {
+        directory_len(&self.directory.join(name))?
+    }
+
+    /// Regions of points of interest, such as village beds and workstations
+    #[culpa::throws]
+    #[tracing::instrument(skip_all, fields(dimension.kind = %self.kind, dimension.directory = %self.directory))]
+    pub(crate) fn poi_regions(&self) -> impl Iterator<Item = Result<Region>> {
+        regions_in(&self.directory.join("poi"))?
     }
 
     #[culpa::throws]
     #[tracing::instrument(skip_all, fields(dimension.kind = %self.kind, dimension.directory = %self.directory, region.coord = %coord))]
-    pub(crate) fn remove_entity_region(&self, coord: Coord<i64>) {
-        let Coord { x, z } = coord;
-        let path = self
-            .directory
-            .join("entities")
-            .join(format!("r.{x}.{z}.mca"));
-        match std::fs::remove_file(path) {
-            Ok(()) => Ok(()),
-            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
-            res => res,
-        }?;
+    pub(crate) fn remove_entity_region(
+        &self,
+        coord: Coord<i64>,
+        external_files: &ExternalChunkFiles,
+    ) -> u64 {
+        remove_region_file(&self.entity_region_path(coord), external_files)?
+    }
+
+    /// The external files of oversized chunks beside the entity regions
+    #[culpa::throws]
+    #[tracing::instrument(skip_all, fields(dimension.kind = %self.kind, dimension.directory = %self.directory))]
+    pub(crate) fn entity_external_chunk_files(&self) -> ExternalChunkFiles {
+        external_chunk_files_in(&self.directory.join("entities"))?
+    }
+
+    /// Reads a saved data file from this dimension's data directory, if it exists
+    #[culpa::throws]
+    #[tracing::instrument(skip_all, fields(dimension.kind = %self.kind, dimension.directory = %self.directory, saved_data.name = name))]
+    pub(crate) fn saved_data(&self, name: &str) -> Option<Compound> {
+        let path = self.directory.join("data").join(format!("{name}.dat"));
+        if !path.try_exists().context("checking for saved data")? {
+            return None;
+        }
+        Some(read_compound(&path)?)
+    }
+
+    #[culpa::throws]
+    #[tracing::instrument(skip_all, fields(dimension.kind = %self.kind, dimension.directory = %self.directory, saved_data.name = name))]
+    pub(crate) fn save_saved_data(&self, name: &str, data: &Compound) {
+        write_compound(
+            &self.directory.join("data").join(format!("{name}.dat")),
+            data,
+        )?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{external_chunk_files_in, remove_region_file};
+    use crate::data::test_directory;
+    use eyre::Error;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    #[culpa::throws]
+    fn remove_truncated_region() {
+        let directory = test_directory("remove-truncated-region")?;
+        let region = directory.join("r.-1.0.mca");
+        std::fs::write(&region, [0; 100])?;
+        // in the region, and in the neighbouring one
+        std::fs::write(directory.join("c.-1.31.mcc"), [0; 20])?;
+        std::fs::write(directory.join("c.0.0.mcc"), [0; 30])?;
+
+        let external_files = external_chunk_files_in(&directory)?;
+        assert_eq!(remove_region_file(&region, &external_files)?, 120);
+        assert!(!region.exists());
+        assert!(!directory.join("c.-1.31.mcc").exists());
+        assert!(directory.join("c.0.0.mcc").exists());
+        std::fs::remove_dir_all(&directory)?;
     }
 }
@@ -0,0 +1,134 @@
+use eyre::{bail, Error};
+
+use super::{dimension, Compound, Coord};
+
+pub(crate) struct Map {
+    pub(crate) id: u32,
+    pub(crate) data: Compound,
+}
+
+/// Whether a banner or frame marker is positioned in a chunk matching the predicate
+#[culpa::throws]
+fn marker_in(marker: &fastnbt::Value, stale: &impl Fn(Coord<i64>) -> bool) -> bool {
+    let fastnbt::Value::Compound(marker) = marker else {
+        bail!("bad marker")
+    };
+    let Some(fastnbt::Value::Compound(position)) = marker.get("Pos") else {
+        bail!("bad marker Pos")
+    };
+    let (Some(&fastnbt::Value::Int(x)), Some(&fastnbt::Value::Int(z))) =
+        (position.get("X"), position.get("Z"))
+    else {
+        bail!("bad marker Pos")
+    };
+    stale(
+        Coord {
+            x: i64::from(x),
+            z: i64::from(z),
+        }
+        .block_to_chunk(),
+    )
+}
+
+impl Map {
+    #[culpa::throws]
+    fn inner(&self) -> &Compound {
+        let Some(fastnbt::Value::Compound(data)) = self.data.get("data") else {
+            bail!("bad data")
+        };
+        data
+    }
+
+    #[culpa::throws]
+    fn inner_mut(&mut self) -> &mut Compound {
+        let Some(fastnbt::Value::Compound(data)) = self.data.get_mut("data") else {
+            bail!("bad data")
+        };
+        data
+    }
+
+    /// The dimension the map shows, if it is one of the vanilla dimensions
+    #[culpa::throws]
+    #[tracing::instrument(skip(self), fields(map.id = self.id))]
+    pub(crate) fn dimension(&self) -> Option<dimension::Kind> {
+        let Some(dimension) = self.inner()?.get("dimension") else {
+            bail!("missing dimension")
+        };
+        dimension::Kind::from_nbt(dimension).ok()
+    }
+
+    #[culpa::throws]
+    #[tracing::instrument(skip(self), fields(map.id = self.id))]
+    pub(crate) fn locked(&self) -> bool {
+        matches!(self.inner()?.get("locked"), Some(&fastnbt::Value::Byte(1)))
+    }
+
+    /// The chunk each pixel of the map shows, in row order
+    #[culpa::throws]
+    #[tracing::instrument(skip(self), fields(map.id = self.id))]
+    pub(crate) fn pixel_chunks(&self) -> Vec<Coord<i64>> {
+        let data = self.inner()?;
+        let (
+            Some(&fastnbt::Value::Int(x)),
+            Some(&fastnbt::Value::Int(z)),
+            Some(&fastnbt::Value::Byte(scale)),
+        ) = (data.get("xCenter"), data.get("zCenter"), data.get("scale"))
+        else {
+            bail!("bad xCenter/zCenter/scale")
+        };
+        // each pixel covers 2^scale blocks, and pixels are always aligned within a single chunk
+        let size = 1 << scale.clamp(0, 4);
+        let origin = Coord {
+            x: i64::from(x) - 64 * size,
+            z: i64::from(z) - 64 * size,
+        };
+        Vec::from_iter((0..128).flat_map(|z| {
+            (0..128).map(move |x| {
+                Coord {
+                    x: origin.x + x * size,
+                    z: origin.z + z * size,
+                }
+                .block_to_chunk()
+            })
+        }))
+    }
+
+    /// Blanks the pixels, and removes the banner and frame markers, in chunks matching the
+    /// predicate so they are redrawn when next explored, returns how many pixels were cleared
+    #[culpa::throws]
+    #[tracing::instrument(skip_all, fields(map.id = self.id))]
+    pub(crate) fn clear(&mut self, stale: impl Fn(Coord<i64>) -> bool) -> usize {
+        let pixel_chunks = self.pixel_chunks()?;
+        let data = self.inner_mut()?;
+
+        let Some(fastnbt::Value::ByteArray(colors)) = data.remove("colors") else {
+            bail!("bad colors")
+        };
+        let mut colors = colors.into_inner();
+        let mut cleared = 0;
+        for (color, &chunk) in colors.iter_mut().zip(&pixel_chunks) {
+            if *color != 0 && stale(chunk) {
+                *color = 0;
+                cleared += 1;
+            }
+        }
+        data.insert(
+            "colors".into(),
+            fastnbt::Value::ByteArray(fastnbt::ByteArray::new(colors)),
+        );
+
+        for key in ["banners", "frames"] {
+            if let Some(fastnbt::Value::List(markers)) = data.get_mut(key) {
+                let mut kept = Vec::with_capacity(markers.len());
+                for marker in std::mem::take(markers) {
+                    if !marker_in(&marker, &stale)? {
+                        kept.push(marker);
+                    }
+                }
+                *markers = kept;
+            }
+        }
+
+        cleared
+    }
+}
@@ -1,12 +1,17 @@
 use camino::Utf8Path;
-use eyre::Error;
+use eyre::{bail, Error};
+use uuid::Uuid;
 
 mod chunk;
+mod compression;
 mod coord;
 mod coord3;
 pub(crate) mod dimension;
+mod map;
 mod player;
 mod region;
+mod seed;
+mod server_properties;
 mod world;
 
 pub(crate) use self::{
@@ -14,13 +19,76 @@ pub(crate) use self::{
     coord::Coord,
     coord3::Coord3,
     dimension::Dimension,
+    map::Map,
     player::Player,
-    region::Region,
+    region::{Region, Repair},
+    seed::{parse_seed, SeedRecord},
+    server_properties::ServerProperties,
     world::World,
 };
 
 pub(crate) type Compound = std::collections::HashMap<String, fastnbt::Value>;
 
+/// Moves an entity and all its passengers, stopping any movement so they land safely
+#[culpa::throws]
+fn relocate_entity(entity: &mut Compound, position: Coord3) {
+    entity.insert(
+        "Pos".into(),
+        fastnbt::nbt!([position.x, position.y, position.z]),
+    );
+    entity.insert("Motion".into(), fastnbt::nbt!([0.0, 0.0, 0.0]));
+    entity.insert("FallDistance".into(), fastnbt::Value::Float(0.0));
+    if let Some(passengers) = entity.get_mut("Passengers") {
+        let fastnbt::Value::List(passengers) = passengers else {
+            bail!("bad Passengers")
+        };
+        for passenger in passengers {
+            let fastnbt::Value::Compound(passenger) = passenger else {
+                bail!("bad passenger")
+            };
+            relocate_entity(passenger, position)?;
+        }
+    }
+}
+
+/// Decodes a UUID stored as four big-endian ints
+#[culpa::throws]
+fn uuid_from_nbt(value: &fastnbt::Value) -> Uuid {
+    let fastnbt::Value::IntArray(ints) = value else {
+        bail!("bad UUID")
+    };
+    let [a, b, c, d] = ints[..] else {
+        bail!("bad UUID")
+    };
+    let mut bytes = [0; 16];
+    for (bytes, int) in bytes.chunks_exact_mut(4).zip([a, b, c, d]) {
+        bytes.copy_from_slice(&int.to_be_bytes());
+    }
+    Uuid::from_bytes(bytes)
+}
+
+/// An empty directory for a test's files
+#[cfg(test)]
+#[culpa::throws]
+pub(crate) fn test_directory(name: &str) -> camino::Utf8PathBuf {
+    let directory = camino::Utf8PathBuf::try_from(std::env::temp_dir())?
+        .join(format!("fc5-tool-{name}-{}", std::process::id()));
+    // left over from an earlier run that failed
+    std::fs::remove_dir_all(&directory).ok();
+    std::fs::create_dir_all(&directory)?;
+    directory
+}
+
+/// Removes a file, treating it already being gone as success
+#[culpa::throws]
+fn remove_file_if_exists(path: &Utf8Path) {
+    match std::fs::remove_file(path) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        res => res,
+    }?;
+}
+
 #[culpa::throws]
 #[tracing::instrument]
 fn read_compound(path: &Utf8Path) -> Compound {
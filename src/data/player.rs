@@ -1,13 +1,36 @@
 use eyre::{bail, ContextCompat, Error};
+use std::collections::HashSet;
 use uuid::Uuid;
 
-use super::{dimension, Compound, Coord3};
+use super::{dimension, relocate_entity, Compound, Coord3};
 
 pub(crate) struct Player {
     pub(crate) uuid: Uuid,
     pub(crate) data: Compound,
 }
 
+/// First data version using item components (1.20.5)
+const ITEM_COMPONENTS_VERSION: i32 = 3837;
+
+/// First data version storing text components as NBT instead of JSON (1.21.5)
+const NBT_TEXT_VERSION: i32 = 4325;
+
+/// Encodes plain text as a JSON text component
+fn json_text(text: &str) -> String {
+    let mut json = String::from(r#"{"text":""#);
+    for c in text.chars() {
+        match c {
+            '"' => json.push_str(r#"\""#),
+            '\\' => json.push_str(r"\\"),
+            '\n' => json.push_str(r"\n"),
+            c if c.is_control() => json.push_str(&format!(r"\u{:04x}", u32::from(c))),
+            c => json.push(c),
+        }
+    }
+    json.push_str(r#""}"#);
+    json
+}
+
 impl Player {
     #[culpa::throws]
     #[tracing::instrument(skip(self), fields(player.uuid = %self.uuid))]
@@ -33,6 +56,16 @@ impl Player {
         );
     }
 
+    /// Clears the player's momentum and accumulated fall distance
+    #[culpa::throws]
+    #[tracing::instrument(skip(self), fields(player.uuid = %self.uuid))]
+    pub(crate) fn reset_motion(&mut self) {
+        self.data
+            .insert("Motion".into(), fastnbt::nbt!([0.0, 0.0, 0.0]));
+        self.data
+            .insert("FallDistance".into(), fastnbt::Value::Float(0.0));
+    }
+
     #[culpa::throws]
     #[tracing::instrument(skip(self), fields(player.uuid = %self.uuid))]
     pub(crate) fn dimension(&self) -> dimension::Kind {
@@ -45,4 +78,130 @@ impl Player {
     pub(crate) fn set_dimension(&mut self, dimension: dimension::Kind) {
         self.data.insert("Dimension".into(), dimension.nbt());
     }
+
+    #[culpa::throws]
+    #[tracing::instrument(skip(self), fields(player.uuid = %self.uuid))]
+    pub(crate) fn data_version(&self) -> i32 {
+        let Some(&fastnbt::Value::Int(data_version)) = self.data.get("DataVersion") else {
+            bail!("bad DataVersion")
+        };
+        data_version
+    }
+
+    /// Puts a single item into the first free main inventory slot, returning whether there was one
+    #[culpa::throws]
+    #[tracing::instrument(skip(self), fields(player.uuid = %self.uuid))]
+    pub(crate) fn give_item(&mut self, id: &str) -> bool {
+        let item = if self.data_version()? >= ITEM_COMPONENTS_VERSION {
+            fastnbt::nbt!({ "id": id, "count": 1 })
+        } else {
+            fastnbt::nbt!({ "id": id, "Count": 1_i8 })
+        };
+        self.give(item)?
+    }
+
+    /// Puts a written book into the first free main inventory slot, returning whether there was
+    /// one
+    #[culpa::throws]
+    #[tracing::instrument(skip(self, pages), fields(player.uuid = %self.uuid))]
+    pub(crate) fn give_book(&mut self, title: &str, author: &str, pages: &[String]) -> bool {
+        let data_version = self.data_version()?;
+        let item = if data_version >= ITEM_COMPONENTS_VERSION {
+            let pages = Vec::from_iter(pages.iter().map(|page| {
+                if data_version >= NBT_TEXT_VERSION {
+                    fastnbt::nbt!({ "raw": page })
+                } else {
+                    fastnbt::nbt!({ "raw": json_text(page) })
+                }
+            }));
+            fastnbt::nbt!({
+                "id": "minecraft:written_book",
+                "count": 1,
+                "components": {
+                    "minecraft:written_book_content": {
+                        "title": { "raw": title },
+                        "author": author,
+                        "pages": pages,
+                    },
+                },
+            })
+        } else {
+            let pages = Vec::from_iter(pages.iter().map(|page| fastnbt::nbt!(json_text(page))));
+            fastnbt::nbt!({
+                "id": "minecraft:written_book",
+                "Count": 1_i8,
+                "tag": {
+                    "title": title,
+                    "author": author,
+                    "pages": pages,
+                    "resolved": 1_i8,
+                },
+            })
+        };
+        self.give(item)?
+    }
+
+    #[culpa::throws]
+    fn give(&mut self, item: fastnbt::Value) -> bool {
+        let fastnbt::Value::Compound(mut item) = item else {
+            bail!("bad item")
+        };
+        let fastnbt::Value::List(inventory) = self
+            .data
+            .entry("Inventory".into())
+            .or_insert_with(|| fastnbt::Value::List(Vec::new()))
+        else {
+            bail!("bad Inventory")
+        };
+        let used = HashSet::<i64>::from_iter(inventory.iter().filter_map(|item| match item {
+            fastnbt::Value::Compound(item) => item.get("Slot").and_then(fastnbt::Value::as_i64),
+            _ => None,
+        }));
+        // hotbar and main inventory, armor and offhand use other slot numbers
+        let Some(slot) = (0..36_i8).find(|&slot| !used.contains(&i64::from(slot))) else {
+            return false;
+        };
+        item.insert("Slot".into(), fastnbt::Value::Byte(slot));
+        inventory.push(fastnbt::Value::Compound(item));
+        true
+    }
+
+    /// The entity id of the root vehicle the player was riding when saved, if any
+    #[culpa::throws]
+    #[tracing::instrument(skip(self), fields(player.uuid = %self.uuid))]
+    pub(crate) fn vehicle(&self) -> Option<String> {
+        let Some(root_vehicle) = self.data.get("RootVehicle") else {
+            return None;
+        };
+        let fastnbt::Value::Compound(root_vehicle) = root_vehicle else {
+            bail!("bad RootVehicle")
+        };
+        let Some(fastnbt::Value::Compound(entity)) = root_vehicle.get("Entity") else {
+            bail!("bad RootVehicle.Entity")
+        };
+        let Some(fastnbt::Value::String(id)) = entity.get("id") else {
+            bail!("bad RootVehicle.Entity.id")
+        };
+        Some(id.clone())
+    }
+
+    /// Moves the root vehicle and all of its passengers along with the player
+    #[culpa::throws]
+    #[tracing::instrument(skip(self), fields(player.uuid = %self.uuid, position = %position))]
+    pub(crate) fn set_vehicle_position(&mut self, position: Coord3) {
+        let Some(fastnbt::Value::Compound(root_vehicle)) = self.data.get_mut("RootVehicle") else {
+            bail!("bad RootVehicle")
+        };
+        let Some(fastnbt::Value::Compound(entity)) = root_vehicle.get_mut("Entity") else {
+            bail!("bad RootVehicle.Entity")
+        };
+        relocate_entity(entity, position)?;
+    }
+
+    /// Removes the player from their vehicle, discarding the vehicle and its other passengers
+    #[culpa::throws]
+    #[tracing::instrument(skip(self), fields(player.uuid = %self.uuid))]
+    pub(crate) fn dismount(&mut self) {
+        self.data.remove("RootVehicle");
+    }
 }
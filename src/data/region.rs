@@ -1,16 +1,133 @@
-use camino::Utf8PathBuf;
-use eyre::{Context, ContextCompat, Error, Result};
-use std::fmt::Debug;
+use camino::{Utf8Path, Utf8PathBuf};
+use eyre::{bail, ensure, Context, ContextCompat, Error, Result};
+use std::{
+    collections::BTreeSet,
+    fmt::Debug,
+    io::{Read, Seek, SeekFrom, Write},
+    time::SystemTime,
+};
 
 use super::{
+    compression::Compression,
     coord::{make_absolute, make_relative},
-    Chunk, Coord,
+    remove_file_if_exists, Chunk, Compound, Coord,
 };
 
+const SECTOR_SIZE: usize = 4096;
+const CHUNK_COUNT: usize = 32 * 32;
+/// Chunk data starts with its length (including the compression byte) and compression byte
+const CHUNK_HEADER_LEN: usize = 5;
+/// The most sectors a location can describe, bigger chunks are stored in an external file
+const MAX_SECTORS: usize = 255;
+/// Set on the compression byte when the chunk's data is in an external `c.x.z.mcc` file
+const EXTERNAL_FLAG: u8 = 0x80;
+
+/// Where a chunk's data is stored, in sectors from the start of the file
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+struct Location {
+    offset: u32,
+    sectors: u8,
+}
+
+impl Location {
+    fn from_bytes([a, b, c, sectors]: [u8; 4]) -> Self {
+        Self {
+            offset: u32::from_be_bytes([0, a, b, c]),
+            sectors,
+        }
+    }
+
+    fn to_bytes(self) -> [u8; 4] {
+        let [_, a, b, c] = self.offset.to_be_bytes();
+        [a, b, c, self.sectors]
+    }
+
+    fn is_empty(self) -> bool {
+        self.offset == 0 || self.sectors == 0
+    }
+
+    #[culpa::throws]
+    fn byte_offset(self) -> u64 {
+        u64::from(self.offset) * u64::try_from(SECTOR_SIZE)?
+    }
+
+    #[culpa::throws]
+    fn sector_range(self) -> std::ops::Range<usize> {
+        let start = usize::try_from(self.offset)?;
+        start..(start + usize::from(self.sectors))
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+struct ChunkHeader {
+    len: usize,
+    compression: Compression,
+    external: bool,
+}
+
+/// Something wrong with a chunk found by [`Region::check`]
+#[derive(Clone, Debug)]
+pub(crate) enum Problem {
+    /// The chunk's sectors are in the region header or past the end of the file
+    OutOfBounds,
+    /// The chunk's sectors overlap those of an earlier chunk
+    Overlapping {
+        other: Coord<i64>,
+    },
+    BadLength(usize),
+    BadCompression(u8),
+    /// The chunk's data could not be read, decompressed or parsed
+    BadData(String),
+    /// The chunk's data says it is a different chunk than the one it is stored as
+    WrongPosition {
+        stored: Coord<i64>,
+    },
+}
+
+impl std::fmt::Display for Problem {
+    #[culpa::throws(std::fmt::Error)]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) {
+        match self {
+            Problem::OutOfBounds => write!(f, "sectors are outside the region file")?,
+            Problem::Overlapping { other } => write!(f, "sectors overlap chunk {other}")?,
+            Problem::BadLength(len) => write!(f, "bad length {len}")?,
+            Problem::BadCompression(compression) => {
+                write!(f, "unknown compression type {compression}")?;
+            }
+            Problem::BadData(error) => write!(f, "unreadable data: {error}")?,
+            Problem::WrongPosition { stored } => write!(f, "data is for chunk {stored}")?,
+        }
+    }
+}
+
+/// What [`Region::repair`] did about a problem
+#[derive(Copy, Clone, Debug)]
+pub(crate) enum Repair {
+    Dropped,
+    Relocated(Coord<i64>),
+    /// Repairing an earlier problem already fixed this one
+    Unneeded,
+}
+
+/// An open region file
+///
+/// Chunk data is written straight to the file, but the header is only written back by
+/// [`Region::flush`] or when the region is dropped, so changing many chunks rewrites it once.
+///
+/// Until then the header in the file may still point at sectors and external files the region no
+/// longer uses, so those are neither reused nor removed before it is written back.
 pub(crate) struct Region {
     pub(crate) coord: Coord<i64>,
     pub(crate) path: Utf8PathBuf,
-    region: fastanvil::Region<std::fs::File>,
+    file: std::fs::File,
+    locations: Vec<Location>,
+    timestamps: Vec<u32>,
+    /// Whether `locations` or `timestamps` differ from the header in the file
+    dirty: bool,
+    /// Locations replaced since the header was last written, which it may still point at
+    unflushed_free: Vec<Location>,
+    /// External chunk files to remove once the header no longer points at them
+    unflushed_removals: Vec<Utf8PathBuf>,
 }
 
 impl Debug for Region {
@@ -23,6 +140,66 @@ impl Debug for Region {
     }
 }
 
+impl Drop for Region {
+    fn drop(&mut self) {
+        if let Err(error) = self.flush() {
+            tracing::error!(region.path = %self.path, "Failed to write back region header: {error:#}");
+        }
+    }
+}
+
+/// Index of a chunk in the region header
+#[culpa::throws]
+fn index(relative_coord: Coord<usize>) -> usize {
+    ensure!(
+        relative_coord.x < 32 && relative_coord.z < 32,
+        "chunk outside region"
+    );
+    relative_coord.x + relative_coord.z * 32
+}
+
+fn relative_coord(index: usize) -> Coord<usize> {
+    Coord {
+        x: index % 32,
+        z: index / 32,
+    }
+}
+
+fn header_bytes(locations: &[Location], timestamps: &[u32]) -> Vec<u8> {
+    let mut header = Vec::with_capacity(SECTOR_SIZE * 2);
+    for location in locations {
+        header.extend_from_slice(&location.to_bytes());
+    }
+    for timestamp in timestamps {
+        header.extend_from_slice(&timestamp.to_be_bytes());
+    }
+    header
+}
+
+#[culpa::throws]
+fn unix_timestamp() -> u32 {
+    u32::try_from(
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)?
+            .as_secs(),
+    )?
+}
+
+/// Damaged chunks and regions are moved here instead of being deleted so they can be inspected or
+/// restored by hand, e.g. `world/fc5-tool-quarantine/region` for `world/region/r.0.0.mca`
+#[culpa::throws]
+fn quarantine_directory(region_path: &Utf8Path) -> Utf8PathBuf {
+    let region_directory = region_path.parent().context("region has no directory")?;
+    let directory = region_directory
+        .parent()
+        .context("region directory has no parent")?
+        .join("fc5-tool-quarantine")
+        .join(region_directory.file_name().context("missing dirname")?);
+    std::fs::create_dir_all(&directory)
+        .with_context(|| format!("creating quarantine directory {directory}"))?;
+    directory
+}
+
 impl Region {
     #[culpa::throws]
     #[tracing::instrument]
@@ -33,41 +210,495 @@ impl Region {
             res => res,
         }?;
         let coord = Coord::from_region_file(path.file_name().context("missing filename")?)?;
-        let file = std::fs::OpenOptions::new()
+        let mut file = std::fs::OpenOptions::new()
             .read(true)
             .write(true)
             .open(&path)
             .context("opening region file")?;
-        let region = fastanvil::Region::from_stream(file).context("initializing region")?;
+        let mut header = vec![0; SECTOR_SIZE * 2];
+        file.read_exact(&mut header)
+            .context("reading region header")?;
+        let (locations, timestamps) = header.split_at(SECTOR_SIZE);
+        let locations = Vec::from_iter(locations.chunks_exact(4).map(|bytes| {
+            Location::from_bytes(bytes.try_into().expect("chunks_exact gives 4 bytes"))
+        }));
+        let timestamps = Vec::from_iter(timestamps.chunks_exact(4).map(|bytes| {
+            u32::from_be_bytes(bytes.try_into().expect("chunks_exact gives 4 bytes"))
+        }));
         Some(Self {
             coord,
             path,
-            region,
+            file,
+            locations,
+            timestamps,
+            dirty: false,
+            unflushed_free: Vec::new(),
+            unflushed_removals: Vec::new(),
         })
     }
 
+    #[culpa::throws]
+    #[tracing::instrument]
+    pub(super) fn create(path: Utf8PathBuf) -> Self {
+        let coord = Coord::from_region_file(path.file_name().context("missing filename")?)?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).context("creating region dir")?;
+        }
+        let mut file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .context("creating region file")?;
+        // locations and timestamps
+        file.write_all(&[0; SECTOR_SIZE * 2])
+            .context("writing region header")?;
+        Self {
+            coord,
+            path,
+            file,
+            locations: vec![Location::default(); CHUNK_COUNT],
+            timestamps: vec![0; CHUNK_COUNT],
+            dirty: false,
+            unflushed_free: Vec::new(),
+            unflushed_removals: Vec::new(),
+        }
+    }
+
+    /// Reads the length and compression type stored before a chunk's data
+    #[culpa::throws]
+    fn chunk_header(&mut self, location: Location) -> ChunkHeader {
+        self.file.seek(SeekFrom::Start(location.byte_offset()?))?;
+        let mut header = [0; CHUNK_HEADER_LEN];
+        self.file
+            .read_exact(&mut header)
+            .context("reading chunk header")?;
+        let [l0, l1, l2, l3, compression] = header;
+        let len = usize::try_from(u32::from_be_bytes([l0, l1, l2, l3]))?;
+        ensure!(len > 0, "empty chunk");
+        ensure!(
+            len - 1 + CHUNK_HEADER_LEN <= usize::from(location.sectors) * SECTOR_SIZE,
+            "chunk longer than its sectors"
+        );
+        ChunkHeader {
+            len: len - 1,
+            compression: Compression::from_id(compression & !EXTERNAL_FLAG)?,
+            external: compression & EXTERNAL_FLAG != 0,
+        }
+    }
+
+    /// Oversized chunks are stored next to the region file, named by their absolute coordinate
+    #[culpa::throws]
+    fn external_path(&self, relative_coord: Coord<usize>) -> Utf8PathBuf {
+        let Coord { x, z } = make_absolute(self.coord, relative_coord)?;
+        self.path.with_file_name(format!("c.{x}.{z}.mcc"))
+    }
+
+    #[culpa::throws]
+    fn read_chunk(&mut self, relative_coord: Coord<usize>) -> Option<(Compression, Vec<u8>)> {
+        let location = self.locations[index(relative_coord)?];
+        if location.is_empty() {
+            return None;
+        }
+        let header = self.chunk_header(location)?;
+        let data = if header.external {
+            let path = self.external_path(relative_coord)?;
+            std::fs::read(&path).with_context(|| format!("reading external chunk {path}"))?
+        } else {
+            let mut data = vec![0; header.len];
+            self.file
+                .read_exact(&mut data)
+                .context("reading chunk data")?;
+            data
+        };
+        Some((header.compression, header.compression.decompress(&data)?))
+    }
+
+    /// The compression to use for chunks that don't have one yet, matching what the rest of the
+    /// region uses so new chunks follow the server's `region-file-compression`
+    #[culpa::throws]
+    fn default_compression(&mut self) -> Compression {
+        let Some(location) = self.locations.iter().copied().find(|l| !l.is_empty()) else {
+            return Compression::Zlib;
+        };
+        self.chunk_header(location)?.compression
+    }
+
+    /// Finds space for a chunk, reusing its current sectors if they are big enough
+    #[culpa::throws]
+    fn allocate(&self, index: usize, sectors: u8) -> Location {
+        let current = self.locations[index];
+        if !current.is_empty() && current.sectors >= sectors {
+            return Location {
+                offset: current.offset,
+                sectors,
+            };
+        }
+
+        // header sectors are always in use, and so are sectors the header in the file may still
+        // point at, including this chunk's current ones
+        let mut used = vec![true, true];
+        for location in self.locations.iter().chain(&self.unflushed_free) {
+            if location.is_empty() {
+                continue;
+            }
+            let range = location.sector_range()?;
+            if used.len() < range.end {
+                used.resize(range.end, false);
+            }
+            used[range].fill(true);
+        }
+
+        let needed = usize::from(sectors);
+        let mut start = 0;
+        for (i, &used) in used.iter().enumerate() {
+            if used {
+                start = i + 1;
+            } else if i + 1 - start == needed {
+                break;
+            }
+        }
+        Location {
+            offset: u32::try_from(start)?,
+            sectors,
+        }
+    }
+
+    fn write_header(&mut self, index: usize, location: Location, timestamp: u32) {
+        let replaced = self.locations[index];
+        if !replaced.is_empty() && replaced != location {
+            self.unflushed_free.push(replaced);
+        }
+        self.locations[index] = location;
+        self.timestamps[index] = timestamp;
+        self.dirty = true;
+    }
+
+    /// Writes the header back to the file if any chunk changed since it was last written
+    #[culpa::throws]
+    #[tracing::instrument(skip_all, fields(region.path = %self.path, region.coord = %self.coord))]
+    pub(crate) fn flush(&mut self) {
+        if !self.dirty {
+            return;
+        }
+        let header = header_bytes(&self.locations, &self.timestamps);
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file
+            .write_all(&header)
+            .context("writing region header")?;
+        self.file.sync_data().context("syncing region")?;
+        self.dirty = false;
+        tracing::trace!("Wrote region header");
+        self.header_written()?;
+    }
+
+    /// Frees what the header in the file pointed at before it was last written
+    #[culpa::throws]
+    fn header_written(&mut self) {
+        self.unflushed_free.clear();
+        for path in std::mem::take(&mut self.unflushed_removals) {
+            remove_file_if_exists(&path).with_context(|| format!("removing {path}"))?;
+            tracing::debug!(external_path = %path, "Removed external chunk file");
+        }
+    }
+
+    #[culpa::throws]
+    fn write_chunk(&mut self, relative_coord: Coord<usize>, data: &[u8]) {
+        let index = index(relative_coord)?;
+        let current = self.locations[index];
+        let (compression, was_external) = if current.is_empty() {
+            (self.default_compression()?, false)
+        } else {
+            let header = self.chunk_header(current)?;
+            (header.compression, header.external)
+        };
+
+        let compressed = compression.compress(data)?;
+        let external_path = self.external_path(relative_coord)?;
+        let external = CHUNK_HEADER_LEN + compressed.len() > MAX_SECTORS * SECTOR_SIZE;
+        let mut buffer = Vec::with_capacity(CHUNK_HEADER_LEN + compressed.len() + SECTOR_SIZE);
+        if external {
+            // the external file is complete before the region points at it
+            self.unflushed_removals
+                .retain(|path| path != &external_path);
+            std::fs::write(&external_path, &compressed)
+                .with_context(|| format!("writing external chunk {external_path}"))?;
+            buffer.extend_from_slice(&1_u32.to_be_bytes());
+            buffer.push(compression.id() | EXTERNAL_FLAG);
+            tracing::debug!(%external_path, "Wrote oversized chunk to external file");
+        } else {
+            buffer.extend_from_slice(&u32::try_from(compressed.len() + 1)?.to_be_bytes());
+            buffer.push(compression.id());
+            buffer.extend_from_slice(&compressed);
+        }
+        buffer.resize(buffer.len().div_ceil(SECTOR_SIZE) * SECTOR_SIZE, 0);
+
+        let Ok(sectors) = u8::try_from(buffer.len() / SECTOR_SIZE) else {
+            bail!("chunk is too large for a region file")
+        };
+        let location = self.allocate(index, sectors)?;
+        tracing::trace!(%compression, external, location.offset = location.offset, location.sectors = location.sectors, "Writing chunk");
+
+        self.file.seek(SeekFrom::Start(location.byte_offset()?))?;
+        self.file.write_all(&buffer).context("writing chunk data")?;
+        self.write_header(index, location, unix_timestamp()?);
+
+        if was_external && !external {
+            tracing::debug!(%external_path, "Chunk shrank, removing its external file once flushed");
+            self.unflushed_removals.push(external_path);
+        }
+    }
+
+    /// Checks every chunk in the region, returning the problems found
+    ///
+    /// Problems with a chunk's own data are listed before overlaps, so repairing in order drops
+    /// broken chunks before deciding which of two overlapping chunks to keep.
+    #[culpa::throws]
+    #[tracing::instrument(skip_all, fields(region.path = %self.path, region.coord = %self.coord))]
+    pub(crate) fn check(&mut self) -> Vec<(Coord<i64>, Problem)> {
+        let file_sectors = usize::try_from(self.file.metadata()?.len())?.div_ceil(SECTOR_SIZE);
+
+        let mut problems = Vec::new();
+        let mut in_bounds = Vec::new();
+        for index in 0..CHUNK_COUNT {
+            let location = self.locations[index];
+            if location.is_empty() {
+                continue;
+            }
+            let relative_coord = relative_coord(index);
+            let absolute_coord = make_absolute(self.coord, relative_coord)?;
+            let sectors = location.sector_range()?;
+            if sectors.start < 2 || sectors.end > file_sectors {
+                problems.push((absolute_coord, Problem::OutOfBounds));
+                continue;
+            }
+            in_bounds.push((absolute_coord, sectors));
+            if let Some(problem) = self.check_chunk(relative_coord, absolute_coord, location)? {
+                problems.push((absolute_coord, problem));
+            }
+        }
+
+        let mut owners = vec![None; file_sectors];
+        for (absolute_coord, sectors) in in_bounds {
+            let mut overlapped = BTreeSet::new();
+            for owner in &mut owners[sectors] {
+                match *owner {
+                    Some(other) => {
+                        overlapped.insert(other);
+                    }
+                    None => *owner = Some(absolute_coord),
+                }
+            }
+            for other in overlapped {
+                problems.push((absolute_coord, Problem::Overlapping { other }));
+            }
+        }
+
+        problems
+    }
+
+    #[culpa::throws]
+    fn check_chunk(
+        &mut self,
+        relative_coord: Coord<usize>,
+        absolute_coord: Coord<i64>,
+        location: Location,
+    ) -> Option<Problem> {
+        self.file.seek(SeekFrom::Start(location.byte_offset()?))?;
+        let mut header = [0; CHUNK_HEADER_LEN];
+        self.file
+            .read_exact(&mut header)
+            .context("reading chunk header")?;
+        let [l0, l1, l2, l3, compression] = header;
+        let len = usize::try_from(u32::from_be_bytes([l0, l1, l2, l3]))?;
+        if len == 0 || len - 1 + CHUNK_HEADER_LEN > usize::from(location.sectors) * SECTOR_SIZE {
+            return Some(Problem::BadLength(len));
+        }
+        if Compression::from_id(compression & !EXTERNAL_FLAG).is_err() {
+            return Some(Problem::BadCompression(compression));
+        }
+
+        let chunk = self.read_chunk(relative_coord).and_then(|data| {
+            let (_, data) = data.context("chunk is missing")?;
+            Chunk::parse(relative_coord, absolute_coord, &data)
+        });
+        match chunk {
+            Err(error) => Some(Problem::BadData(format!("{error:#}"))),
+            Ok(chunk) => match chunk.stored_position() {
+                Some(stored) if stored != absolute_coord => Some(Problem::WrongPosition { stored }),
+                _ => None,
+            },
+        }
+    }
+
+    /// Fixes a problem found by [`Region::check`], moving a chunk to the position its data says it
+    /// is at if that slot is free, otherwise dropping it
+    #[culpa::throws]
+    #[tracing::instrument(skip_all, fields(region.path = %self.path, region.coord = %self.coord, chunk.absolute_coord = %absolute_coord))]
+    pub(crate) fn repair(&mut self, absolute_coord: Coord<i64>, problem: &Problem) -> Repair {
+        let relative_coord = make_relative(self.coord, absolute_coord)?;
+        let location = self.locations[index(relative_coord)?];
+        if location.is_empty() {
+            return Repair::Unneeded;
+        }
+
+        match *problem {
+            Problem::Overlapping { other } => {
+                let other = self.locations[index(make_relative(self.coord, other)?)?];
+                let (ours, theirs) = (location.sector_range()?, other.sector_range()?);
+                if other.is_empty() || ours.end <= theirs.start || theirs.end <= ours.start {
+                    return Repair::Unneeded;
+                }
+            }
+            Problem::WrongPosition { stored } => {
+                if let Ok(target) = make_relative(self.coord, stored) {
+                    if index(target).is_ok_and(|target| self.locations[target].is_empty()) {
+                        self.relocate(relative_coord, target)?;
+                        return Repair::Relocated(stored);
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        self.remove_chunk(absolute_coord)?;
+        Repair::Dropped
+    }
+
+    #[culpa::throws]
+    fn relocate(&mut self, from: Coord<usize>, to: Coord<usize>) {
+        let (from_index, to_index) = (index(from)?, index(to)?);
+        let location = self.locations[from_index];
+        if self.chunk_header(location)?.external {
+            // the header in the file still points at the old slot until it is written back, so the
+            // old file is kept until then and the new one is complete before it is
+            let (from_path, to_path) = (self.external_path(from)?, self.external_path(to)?);
+            self.unflushed_removals.retain(|path| path != &to_path);
+            std::fs::copy(&from_path, &to_path)
+                .with_context(|| format!("copying external chunk {from_path}"))?;
+            self.unflushed_removals.push(from_path);
+        }
+        self.write_header(to_index, location, unix_timestamp()?);
+        self.write_header(from_index, Location::default(), 0);
+    }
+
+    /// Rewrites the region with its chunks packed tightly after the header, returning how many
+    /// bytes were reclaimed
+    ///
+    /// The packed region is written to a temporary file that then replaces the original, so a
+    /// crash part way through leaves the original untouched.
+    #[culpa::throws]
+    #[tracing::instrument(skip_all, fields(region.path = %self.path, region.coord = %self.coord))]
+    pub(crate) fn compact(&mut self) -> u64 {
+        let old_len = self.file.metadata()?.len();
+
+        let mut packed = Vec::with_capacity(CHUNK_COUNT);
+        let mut next_offset = 2;
+        for index in 0..CHUNK_COUNT {
+            let location = self.locations[index];
+            if location.is_empty() {
+                continue;
+            }
+            // only keep the sectors the chunk actually uses, it may have shrunk in place
+            let len = CHUNK_HEADER_LEN + self.chunk_header(location)?.len;
+            let sectors = u8::try_from(len.div_ceil(SECTOR_SIZE))?;
+            packed.push((index, location, len, next_offset));
+            next_offset += u32::from(sectors);
+        }
+
+        let new_len = u64::from(next_offset) * u64::try_from(SECTOR_SIZE)?;
+        if new_len >= old_len {
+            tracing::trace!("Region is already compact");
+            self.flush()?;
+            return 0;
+        }
+
+        let mut locations = vec![Location::default(); CHUNK_COUNT];
+        for &(index, _, len, offset) in &packed {
+            locations[index] = Location {
+                offset,
+                sectors: u8::try_from(len.div_ceil(SECTOR_SIZE))?,
+            };
+        }
+
+        let temp_path = self.path.with_extension("mca.tmp");
+        let mut temp = std::fs::File::create(&temp_path).context("creating compacted region")?;
+        // the in-memory header may not have been written back yet, it is the current one
+        temp.write_all(&header_bytes(&locations, &self.timestamps))?;
+        let mut buffer = Vec::new();
+        for &(_, location, len, _) in &packed {
+            buffer.resize(len, 0);
+            self.file.seek(SeekFrom::Start(location.byte_offset()?))?;
+            self.file
+                .read_exact(&mut buffer)
+                .context("reading chunk data")?;
+            buffer.resize(len.div_ceil(SECTOR_SIZE) * SECTOR_SIZE, 0);
+            temp.write_all(&buffer)
+                .context("writing compacted region")?;
+        }
+        temp.sync_all().context("syncing compacted region")?;
+        drop(temp);
+
+        std::fs::rename(&temp_path, &self.path).context("replacing region")?;
+        self.file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&self.path)
+            .context("opening compacted region")?;
+        self.locations = locations;
+        self.dirty = false;
+        self.header_written()?;
+
+        let reclaimed = old_len - new_len;
+        tracing::debug!(region.reclaimed_bytes = reclaimed, "Compacted region");
+        reclaimed
+    }
+
     #[culpa::throws]
     #[tracing::instrument(skip_all, fields(region.path = %self.path, region.coord = %self.coord, chunk.absolute_coord = %absolute_coord))]
     pub(crate) fn chunk(&mut self, absolute_coord: Coord<i64>) -> Option<Chunk> {
         let relative_coord = make_relative(self.coord, absolute_coord)?;
-        let Some(data) = self
-            .region
-            .read_chunk(relative_coord.x, relative_coord.z)
-            .context("reading chunk")?
+        let Some((compression, data)) = self.read_chunk(relative_coord).context("reading chunk")?
         else {
             return None;
         };
+        tracing::trace!(%compression, "Read chunk");
         Some(Chunk::parse(relative_coord, absolute_coord, &data)?)
     }
 
+    /// Creates a new entity chunk with no entities, it is only written once saved
+    #[culpa::throws]
+    #[tracing::instrument(skip_all, fields(region.path = %self.path, region.coord = %self.coord, chunk.absolute_coord = %absolute_coord))]
+    pub(crate) fn new_entity_chunk(
+        &self,
+        absolute_coord: Coord<i64>,
+        data_version: fastnbt::Value,
+    ) -> Chunk {
+        let relative_coord = make_relative(self.coord, absolute_coord)?;
+        let position = vec![
+            i32::try_from(absolute_coord.x)?,
+            i32::try_from(absolute_coord.z)?,
+        ];
+        Chunk {
+            relative_coord,
+            absolute_coord,
+            data: Compound::from_iter([
+                ("DataVersion".into(), data_version),
+                (
+                    "Position".into(),
+                    fastnbt::Value::IntArray(fastnbt::IntArray::new(position)),
+                ),
+                ("Entities".into(), fastnbt::Value::List(Vec::new())),
+            ]),
+        }
+    }
+
     #[culpa::throws]
     #[tracing::instrument(skip_all, fields(region.path = %self.path, region.coord = %self.coord, chunk.relative_coord = %chunk.relative_coord))]
     pub(crate) fn save_chunk(&mut self, chunk: &Chunk) {
-        self.region.write_chunk(
-            chunk.relative_coord.x,
-            chunk.relative_coord.z,
-            &chunk.serialize()?,
-        )?;
+        self.write_chunk(chunk.relative_coord, &chunk.serialize()?)
+            .context("writing chunk")?;
     }
 
     #[culpa::throws]
@@ -75,20 +706,326 @@ impl Region {
     pub(crate) fn remove_chunk(&mut self, absolute_coord: Coord<i64>) {
         let relative_coord = make_relative(self.coord, absolute_coord)?;
         tracing::Span::current().record("relative_coord", relative_coord.to_string());
-        self.region
-            .remove_chunk(relative_coord.x, relative_coord.z)
-            .context("removing chunk")?;
+        let index = index(relative_coord)?;
+        self.write_header(index, Location::default(), 0);
+        // the chunk may have been oversized, its external file would be orphaned
+        let external_path = self.external_path(relative_coord)?;
+        self.unflushed_removals.push(external_path);
+    }
+
+    /// Copies whatever is stored for a chunk into the quarantine directory, then removes it from
+    /// the region, returning where it was copied to
+    ///
+    /// The chunk may be damaged in any way, so the raw sectors are copied without being parsed,
+    /// cut short where they run past the end of the file.
+    #[culpa::throws]
+    #[tracing::instrument(skip_all, fields(region.path = %self.path, region.coord = %self.coord, chunk.absolute_coord = %absolute_coord))]
+    pub(crate) fn quarantine_chunk(&mut self, absolute_coord: Coord<i64>) -> Utf8PathBuf {
+        let relative_coord = make_relative(self.coord, absolute_coord)?;
+        let location = self.locations[index(relative_coord)?];
+        let Coord { x, z } = absolute_coord;
+        let timestamp = unix_timestamp()?;
+        let directory = quarantine_directory(&self.path)?;
+        let path = directory.join(format!("c.{x}.{z}.{timestamp}.bin"));
+
+        let mut data = Vec::new();
+        if !location.is_empty() {
+            self.file.seek(SeekFrom::Start(location.byte_offset()?))?;
+            (&mut self.file)
+                .take(u64::from(location.sectors) * u64::try_from(SECTOR_SIZE)?)
+                .read_to_end(&mut data)
+                .context("reading chunk sectors")?;
+        }
+        std::fs::write(&path, &data).with_context(|| format!("writing {path}"))?;
+
+        let external_path = self.external_path(relative_coord)?;
+        match std::fs::rename(
+            &external_path,
+            directory.join(format!("c.{x}.{z}.{timestamp}.mcc")),
+        ) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            res => res.with_context(|| format!("moving external chunk {external_path}"))?,
+        }
+
+        self.remove_chunk(absolute_coord)?;
+        tracing::debug!(quarantine.path = %path, "Quarantined chunk");
+        path
     }
 
+    /// Moves a whole region file into the quarantine directory, for regions too damaged to open,
+    /// returning where it was moved to
+    #[culpa::throws]
+    #[tracing::instrument]
+    pub(crate) fn quarantine(path: &Utf8Path) -> Utf8PathBuf {
+        let name = path.file_name().context("missing filename")?;
+        let new_path = quarantine_directory(path)?
+            .join(format!("{name}.{timestamp}", timestamp = unix_timestamp()?));
+        std::fs::rename(path, &new_path).with_context(|| format!("moving {path}"))?;
+        tracing::debug!(quarantine.path = %new_path, "Quarantined region");
+        new_path
+    }
+
+    #[tracing::instrument(skip_all, fields(region.path = %self.path, region.coord = %self.coord))]
+    pub(crate) fn chunks(&self) -> impl Iterator<Item = Result<Coord<i64>>> + '_ {
+        self.locations
+            .iter()
+            .enumerate()
+            .filter(|(_, location)| !location.is_empty())
+            .map(|(index, _)| Ok(make_absolute(self.coord, relative_coord(index))?))
+    }
+
+    /// How many bytes each chunk takes up in the file, going by the sectors reserved for it
+    #[tracing::instrument(skip_all, fields(region.path = %self.path, region.coord = %self.coord))]
+    pub(crate) fn chunk_lens(&self) -> impl Iterator<Item = Result<(Coord<i64>, u64)>> + '_ {
+        self.locations
+            .iter()
+            .enumerate()
+            .filter(|(_, location)| !location.is_empty())
+            .map(|(index, location)| {
+                Ok((
+                    make_absolute(self.coord, relative_coord(index))?,
+                    u64::from(location.sectors) * u64::try_from(SECTOR_SIZE)?,
+                ))
+            })
+    }
+
+    /// Bytes in the file used by neither the header nor any chunk, which compacting would reclaim
+    #[culpa::throws]
     #[tracing::instrument(skip_all, fields(region.path = %self.path, region.coord = %self.coord))]
-    pub(crate) fn chunks(&mut self) -> impl Iterator<Item = Result<Coord<i64>>> + '_ {
-        self.region.iter().map(|chunk| {
-            let chunk = chunk.context("reading chunk")?;
-            let relative_coord = Coord {
-                x: chunk.x,
-                z: chunk.z,
+    pub(crate) fn unused_len(&self) -> u64 {
+        let used = self
+            .locations
+            .iter()
+            .filter(|location| !location.is_empty())
+            .map(|location| usize::from(location.sectors))
+            .sum::<usize>()
+            + 2;
+        let used = u64::try_from(used * SECTOR_SIZE)?;
+        self.file.metadata()?.len().saturating_sub(used)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Problem, Region, Repair};
+    use crate::data::{test_directory, Chunk, Coord};
+    use eyre::Error;
+    use pretty_assertions::assert_eq;
+
+    /// An entity chunk too big for a region file, padded with bytes that don't compress
+    #[culpa::throws]
+    fn oversized_chunk(region: &Region, coord: Coord<i64>) -> Chunk {
+        let mut chunk = region.new_entity_chunk(coord, fastnbt::Value::Int(3700))?;
+        let mut state = 0x2545_f491_u32;
+        let padding = Vec::from_iter((0..1_200_000).map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            i8::from_ne_bytes([state.to_ne_bytes()[0]])
+        }));
+        chunk.data.insert(
+            "Padding".into(),
+            fastnbt::Value::ByteArray(fastnbt::ByteArray::new(padding)),
+        );
+        chunk
+    }
+
+    #[test]
+    #[culpa::throws]
+    fn unflushed_removal_keeps_sectors() {
+        let directory = test_directory("unflushed-removal")?;
+        let path = directory.join("r.0.0.mca");
+        let (removed, saved) = (Coord { x: 0, z: 0 }, Coord { x: 1, z: 0 });
+
+        let mut region = Region::create(path.clone())?;
+        let chunk = region.new_entity_chunk(removed, fastnbt::Value::Int(3700))?;
+        region.save_chunk(&chunk)?;
+        region.flush()?;
+
+        region.remove_chunk(removed)?;
+        let chunk = region.new_entity_chunk(saved, fastnbt::Value::Int(3700))?;
+        region.save_chunk(&chunk)?;
+        // as if the process died before the header was written back
+        std::mem::forget(region);
+
+        let mut region = Region::from_path(path)?.expect("region exists");
+        let chunk = region
+            .chunk(removed)?
+            .expect("header in the file still has the chunk");
+        assert_eq!(chunk.stored_position(), Some(removed));
+        drop(region);
+        std::fs::remove_dir_all(&directory)?;
+    }
+
+    #[test]
+    #[culpa::throws]
+    fn chunk_and_unused_lens() {
+        let directory = test_directory("chunk-lens")?;
+        let (first, second) = (Coord { x: 0, z: 0 }, Coord { x: 1, z: 0 });
+
+        let mut region = Region::create(directory.join("r.0.0.mca"))?;
+        for coord in [first, second] {
+            let chunk = region.new_entity_chunk(coord, fastnbt::Value::Int(3700))?;
+            region.save_chunk(&chunk)?;
+        }
+        assert_eq!(
+            Result::<Vec<_>, _>::from_iter(region.chunk_lens())?,
+            [(first, 4096), (second, 4096)]
+        );
+        assert_eq!(region.unused_len()?, 0);
+
+        region.remove_chunk(first)?;
+        region.flush()?;
+        assert_eq!(
+            Result::<Vec<_>, _>::from_iter(region.chunk_lens())?,
+            [(second, 4096)]
+        );
+        assert_eq!(region.unused_len()?, 4096);
+        drop(region);
+        std::fs::remove_dir_all(&directory)?;
+    }
+
+    #[test]
+    #[culpa::throws]
+    fn compact_keeps_chunks() {
+        let directory = test_directory("compact")?;
+        let path = directory.join("r.0.0.mca");
+        let (removed, oversized, kept) = (
+            Coord { x: 0, z: 0 },
+            Coord { x: 1, z: 0 },
+            Coord { x: 2, z: 0 },
+        );
+
+        let mut region = Region::create(path.clone())?;
+        let mut saved = Vec::new();
+        for coord in [removed, oversized, kept] {
+            let chunk = if coord == oversized {
+                oversized_chunk(&region, coord)?
+            } else {
+                region.new_entity_chunk(coord, fastnbt::Value::Int(3700))?
             };
-            Ok(make_absolute(self.coord, relative_coord)?)
-        })
+            region.save_chunk(&chunk)?;
+            saved.push(chunk);
+        }
+        region.flush()?;
+        assert!(directory.join("c.1.0.mcc").exists());
+
+        region.remove_chunk(removed)?;
+        let old_len = std::fs::metadata(&path)?.len();
+        let reclaimed = region.compact()?;
+        drop(region);
+        assert_eq!(reclaimed, 4096);
+        assert_eq!(std::fs::metadata(&path)?.len(), old_len - reclaimed);
+
+        let mut region = Region::from_path(path)?.expect("region exists");
+        assert!(region.chunk(removed)?.is_none());
+        for chunk in &saved[1..] {
+            let read = region.chunk(chunk.absolute_coord)?.expect("chunk is kept");
+            assert_eq!(read.data, chunk.data);
+        }
+        assert!(directory.join("c.1.0.mcc").exists());
+        drop(region);
+        std::fs::remove_dir_all(&directory)?;
+    }
+
+    #[test]
+    #[culpa::throws]
+    fn repair_relocates_wrong_position() {
+        let directory = test_directory("repair-relocate")?;
+        let path = directory.join("r.0.0.mca");
+        let (slot, stored) = (Coord { x: 0, z: 0 }, Coord { x: 3, z: 0 });
+
+        let mut region = Region::create(path.clone())?;
+        let mut chunk = oversized_chunk(&region, stored)?;
+        chunk.relative_coord = Coord { x: 0, z: 0 };
+        chunk.absolute_coord = slot;
+        region.save_chunk(&chunk)?;
+        region.flush()?;
+
+        let problems = region.check()?;
+        assert!(matches!(
+            problems[..],
+            [(coord, Problem::WrongPosition { stored: to })] if coord == slot && to == stored
+        ));
+        assert!(matches!(
+            region.repair(slot, &problems[0].1)?,
+            Repair::Relocated(to) if to == stored
+        ));
+        // the header in the file still points at the old slot, and so at the old external file
+        assert!(directory.join("c.0.0.mcc").exists());
+        region.flush()?;
+        assert!(!directory.join("c.0.0.mcc").exists());
+        drop(region);
+
+        let mut region = Region::from_path(path)?.expect("region exists");
+        assert!(region.chunk(slot)?.is_none());
+        let moved = region.chunk(stored)?.expect("chunk was moved");
+        assert_eq!(moved.data, chunk.data);
+        assert!(region.check()?.is_empty());
+        drop(region);
+        std::fs::remove_dir_all(&directory)?;
+    }
+
+    #[test]
+    #[culpa::throws]
+    fn repair_drops_wrong_position_when_taken() {
+        let directory = test_directory("repair-drop")?;
+        let (slot, stored) = (Coord { x: 0, z: 0 }, Coord { x: 3, z: 0 });
+
+        let mut region = Region::create(directory.join("r.0.0.mca"))?;
+        let chunk = region.new_entity_chunk(stored, fastnbt::Value::Int(3700))?;
+        region.save_chunk(&chunk)?;
+        let mut misplaced = region.new_entity_chunk(stored, fastnbt::Value::Int(3700))?;
+        misplaced.relative_coord = Coord { x: 0, z: 0 };
+        misplaced.absolute_coord = slot;
+        region.save_chunk(&misplaced)?;
+
+        let problems = region.check()?;
+        assert!(matches!(
+            problems[..],
+            [(coord, Problem::WrongPosition { .. })] if coord == slot
+        ));
+        assert!(matches!(
+            region.repair(slot, &problems[0].1)?,
+            Repair::Dropped
+        ));
+        assert!(region.chunk(slot)?.is_none());
+        let kept = region.chunk(stored)?.expect("chunk in the slot is kept");
+        assert_eq!(kept.stored_position(), Some(stored));
+        drop(region);
+        std::fs::remove_dir_all(&directory)?;
+    }
+
+    #[test]
+    #[culpa::throws]
+    fn repair_drops_one_of_overlapping() {
+        let directory = test_directory("repair-overlap")?;
+        let (first, second) = (Coord { x: 0, z: 0 }, Coord { x: 1, z: 0 });
+
+        let mut region = Region::create(directory.join("r.0.0.mca"))?;
+        for coord in [first, second] {
+            let chunk = region.new_entity_chunk(coord, fastnbt::Value::Int(3700))?;
+            region.save_chunk(&chunk)?;
+        }
+        // both chunks point at the first one's sectors
+        region.locations[1] = region.locations[0];
+        region.dirty = true;
+        region.flush()?;
+
+        let problems = region.check()?;
+        assert!(problems.iter().any(|(coord, problem)| *coord == second
+            && matches!(problem, Problem::Overlapping { other } if *other == first)));
+        let mut dropped = 0;
+        for (coord, problem) in &problems {
+            if matches!(region.repair(*coord, problem)?, Repair::Dropped) {
+                dropped += 1;
+            }
+        }
+        assert_eq!(dropped, 1);
+        let kept = region.chunk(first)?.expect("one chunk is kept");
+        assert_eq!(kept.stored_position(), Some(first));
+        assert!(region.chunk(second)?.is_none());
+        drop(region);
+        std::fs::remove_dir_all(&directory)?;
     }
 }
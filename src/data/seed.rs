@@ -0,0 +1,67 @@
+use eyre::{ensure, Error};
+
+/// Java's `String.hashCode`
+fn java_hash(text: &str) -> i32 {
+    text.encode_utf16().fold(0, |hash: i32, unit| {
+        hash.wrapping_mul(31).wrapping_add(i32::from(unit))
+    })
+}
+
+/// Converts a seed as entered in the create world screen (or `level-seed`) to its numeric value,
+/// numeric text is used directly and anything else is hashed, the same way Minecraft does
+#[culpa::throws]
+#[tracing::instrument(level = "debug")]
+pub(crate) fn parse_seed(text: &str) -> i64 {
+    let text = text.trim();
+    ensure!(!text.is_empty(), "empty seed");
+    text.parse().unwrap_or_else(|_| i64::from(java_hash(text)))
+}
+
+/// A seed set on the world by this tool
+#[derive(Copy, Clone, PartialEq, Eq, Debug, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct SeedRecord {
+    /// Which reset cycle this seed was used for, counting from 1
+    pub(crate) cycle: u64,
+
+    /// When the seed was set, in seconds since the Unix epoch
+    pub(crate) timestamp: u64,
+
+    pub(crate) seed: i64,
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+pub(super) struct SeedHistory {
+    #[serde(default)]
+    pub(super) seed: Vec<SeedRecord>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_seed;
+    use eyre::Error;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    #[culpa::throws]
+    fn numeric() {
+        assert_eq!(parse_seed("42")?, 42);
+        assert_eq!(parse_seed(" -42 ")?, -42);
+        assert_eq!(parse_seed("+42")?, 42);
+        assert_eq!(parse_seed("-9223372036854775808")?, i64::MIN);
+    }
+
+    #[test]
+    #[culpa::throws]
+    fn text() {
+        assert_eq!(parse_seed("hello")?, 99_162_322);
+        assert_eq!(parse_seed("Hello World")?, -862_545_276);
+        // out of range numbers are hashed like any other text
+        assert_eq!(parse_seed("9223372036854775808")?, -1_773_151_197);
+    }
+
+    #[test]
+    fn empty() {
+        assert!(parse_seed("  ").is_err());
+    }
+}
@@ -0,0 +1,258 @@
+use camino::{Utf8Path, Utf8PathBuf};
+use eyre::{bail, ensure, Context, Error, Result};
+
+#[derive(Clone, PartialEq, Debug)]
+enum Line {
+    /// A comment, blank line, or anything else kept exactly as it was
+    Other(String),
+    Property {
+        key: String,
+        value: String,
+        raw: String,
+    },
+}
+
+/// A server's `server.properties`, edited in place so comments and ordering are kept
+#[derive(Debug)]
+pub(crate) struct ServerProperties {
+    pub(crate) path: Utf8PathBuf,
+    lines: Vec<Line>,
+    newline: &'static str,
+}
+
+/// Undoes Java properties escapes, `\uXXXX` (with surrogate pairs) and the control characters are
+/// special and any other escaped character stands for itself
+#[culpa::throws]
+fn unescape(text: &str) -> String {
+    fn code_unit(chars: &mut std::str::Chars<'_>) -> Result<u16> {
+        let code: String = chars.take(4).collect();
+        u16::from_str_radix(&code, 16).context("bad unicode escape")
+    }
+
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => result.push('\t'),
+            Some('n') => result.push('\n'),
+            Some('r') => result.push('\r'),
+            Some('f') => result.push('\u{c}'),
+            Some('u') => {
+                let high = code_unit(&mut chars)?;
+                // characters outside the basic multilingual plane are escaped as a UTF-16
+                // surrogate pair, each half its own escape
+                let units = if (0xd800..0xdc00).contains(&high) {
+                    ensure!(
+                        chars.next() == Some('\\') && chars.next() == Some('u'),
+                        "unpaired surrogate in unicode escape"
+                    );
+                    vec![high, code_unit(&mut chars)?]
+                } else {
+                    vec![high]
+                };
+                for c in char::decode_utf16(units) {
+                    result.push(c.context("unpaired surrogate in unicode escape")?);
+                }
+            }
+            Some(c) => result.push(c),
+            None => bail!("line continuations are not supported"),
+        }
+    }
+    result
+}
+
+/// Escapes text the same way Java's `Properties.store` does when writing to a `Writer`
+fn escape(text: &str, is_key: bool) -> String {
+    let mut result = String::with_capacity(text.len());
+    for (i, c) in text.chars().enumerate() {
+        match c {
+            '\\' => result.push_str("\\\\"),
+            '\t' => result.push_str("\\t"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\u{c}' => result.push_str("\\f"),
+            '=' | ':' | '#' | '!' => {
+                result.push('\\');
+                result.push(c);
+            }
+            ' ' if i == 0 || is_key => result.push_str("\\ "),
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+#[culpa::throws]
+fn parse_line(raw: &str) -> Line {
+    let line = raw.trim_start_matches([' ', '\t', '\u{c}']);
+    if line.is_empty() || line.starts_with(['#', '!']) {
+        return Line::Other(raw.to_owned());
+    }
+
+    // the key ends at the first unescaped separator or whitespace
+    let mut escaped = false;
+    let key_end = line
+        .char_indices()
+        .find(|&(_, c)| {
+            let end = !escaped && matches!(c, '=' | ':' | ' ' | '\t' | '\u{c}');
+            escaped = !escaped && c == '\\';
+            end
+        })
+        .map_or(line.len(), |(i, _)| i);
+    let (key, rest) = line.split_at(key_end);
+
+    let rest = rest.trim_start_matches([' ', '\t', '\u{c}']);
+    let rest = rest.strip_prefix(['=', ':']).unwrap_or(rest);
+    let value = rest.trim_start_matches([' ', '\t', '\u{c}']);
+
+    Line::Property {
+        key: unescape(key)?,
+        value: unescape(value)?,
+        raw: raw.to_owned(),
+    }
+}
+
+impl ServerProperties {
+    /// Reads the properties file, if it exists
+    #[culpa::throws]
+    #[tracing::instrument]
+    pub(crate) fn load(path: &Utf8Path) -> Option<Self> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return None,
+            Err(e) => Err(e).context("reading server properties")?,
+        };
+        Some(Self::parse(path, &text)?)
+    }
+
+    #[culpa::throws]
+    fn parse(path: &Utf8Path, text: &str) -> Self {
+        let lines =
+            Result::<Vec<_>, Error>::from_iter(text.lines().enumerate().map(|(i, line)| {
+                parse_line(line).with_context(|| format!("parsing line {}", i + 1))
+            }))?;
+        Self {
+            path: path.to_owned(),
+            lines,
+            newline: if text.contains("\r\n") { "\r\n" } else { "\n" },
+        }
+    }
+
+    /// The value of a property, using the last one if it is set more than once like Java does
+    pub(crate) fn get(&self, key: &str) -> Option<&str> {
+        self.lines.iter().rev().find_map(|line| match line {
+            Line::Property {
+                key: existing_key,
+                value,
+                ..
+            } if existing_key == key => Some(value.as_str()),
+            _ => None,
+        })
+    }
+
+    /// Changes a property where it is, or adds it at the end if it is not set yet
+    pub(crate) fn set(&mut self, key: &str, value: &str) {
+        let new_raw = format!("{}={}", escape(key, true), escape(value, false));
+        let existing = self.lines.iter_mut().rev().find(
+            |line| matches!(line, Line::Property { key: existing_key, .. } if existing_key == key),
+        );
+        let new_line = Line::Property {
+            key: key.to_owned(),
+            value: value.to_owned(),
+            raw: new_raw,
+        };
+        match existing {
+            Some(line) => *line = new_line,
+            None => self.lines.push(new_line),
+        }
+    }
+
+    fn to_text(&self) -> String {
+        let mut text = String::new();
+        for line in &self.lines {
+            match line {
+                Line::Other(raw) | Line::Property { raw, .. } => text.push_str(raw),
+            }
+            text.push_str(self.newline);
+        }
+        text
+    }
+
+    #[culpa::throws]
+    #[tracing::instrument(skip_all, fields(server.properties = %self.path))]
+    pub(crate) fn save(&self) {
+        std::fs::write(&self.path, self.to_text()).context("writing server properties")?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ServerProperties;
+    use camino::Utf8Path;
+    use eyre::Error;
+    use pretty_assertions::assert_eq;
+
+    const TEXT: &str = indoc::indoc! {r"
+        #Minecraft server properties
+        #Mon Jan 01 00:00:00 UTC 2024
+        enable-jmx-monitoring=false
+        level-name=worlds/main\:1
+        level-seed=
+        motd  :  A Minecraft Server
+    "};
+
+    #[test]
+    #[culpa::throws]
+    fn get() {
+        let properties = ServerProperties::parse(Utf8Path::new("server.properties"), TEXT)?;
+        assert_eq!(properties.get("level-name"), Some("worlds/main:1"));
+        assert_eq!(properties.get("level-seed"), Some(""));
+        assert_eq!(properties.get("motd"), Some("A Minecraft Server"));
+        assert_eq!(properties.get("missing"), None);
+    }
+
+    #[test]
+    #[culpa::throws]
+    fn set_keeps_layout() {
+        let mut properties = ServerProperties::parse(Utf8Path::new("server.properties"), TEXT)?;
+        properties.set("level-seed", "-42");
+        properties.set("motd", "reset: #5");
+        properties.set("new-key", " spaced");
+        assert_eq!(
+            properties.to_text(),
+            indoc::indoc! {r"
+                #Minecraft server properties
+                #Mon Jan 01 00:00:00 UTC 2024
+                enable-jmx-monitoring=false
+                level-name=worlds/main\:1
+                level-seed=-42
+                motd=reset\: \#5
+                new-key=\ spaced
+            "}
+        );
+    }
+
+    #[test]
+    #[culpa::throws]
+    fn unicode_escapes() {
+        let properties = ServerProperties::parse(
+            Utf8Path::new("server.properties"),
+            "motd=caf\\u00e9 \\ud83d\\ude00\n",
+        )?;
+        assert_eq!(properties.get("motd"), Some("café 😀"));
+        for text in ["motd=\\ud83d\n", "motd=\\ud83dx\n", "motd=\\ude00\n"] {
+            assert!(ServerProperties::parse(Utf8Path::new("server.properties"), text).is_err());
+        }
+    }
+
+    #[test]
+    fn continuation() {
+        assert!(
+            ServerProperties::parse(Utf8Path::new("server.properties"), "motd=a\\\nb\n").is_err()
+        );
+    }
+}
@@ -1,8 +1,12 @@
 use camino::{Utf8Path, Utf8PathBuf};
 use eyre::{Context, ContextCompat, Error, Result};
+use std::io::Write;
 use uuid::Uuid;
 
-use super::{dimension, read_compound, write_compound, Compound, Dimension, Player};
+use super::{
+    dimension, read_compound, seed::SeedHistory, write_compound, Compound, Dimension, Map, Player,
+    SeedRecord,
+};
 
 #[derive(Debug)]
 pub(crate) struct World {
@@ -32,6 +36,42 @@ impl World {
         write_compound(&self.directory.join("level.dat"), data)?;
     }
 
+    /// Every seed previously set by this tool, oldest first
+    #[culpa::throws]
+    #[tracing::instrument(skip_all, fields(world.directory = %self.directory))]
+    pub(crate) fn seed_history(&self) -> Vec<SeedRecord> {
+        let path = self.directory.join("fc5-tool-seeds.toml");
+        let history = match std::fs::read_to_string(&path) {
+            Ok(history) => history,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Vec::new(),
+            Err(e) => Err(e).context("reading seed history")?,
+        };
+        toml::from_str::<SeedHistory>(&history)
+            .context("parsing seed history")?
+            .seed
+    }
+
+    #[culpa::throws]
+    #[tracing::instrument(skip_all, fields(world.directory = %self.directory, seed.cycle = record.cycle, seed.seed = record.seed))]
+    pub(crate) fn record_seed(&self, record: &SeedRecord) {
+        let SeedRecord {
+            cycle,
+            timestamp,
+            seed,
+        } = record;
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.directory.join("fc5-tool-seeds.toml"))
+            .context("opening seed history")?;
+        // appending tables keeps the file valid toml without rewriting it
+        write!(
+            file,
+            "[[seed]]\ncycle = {cycle}\ntimestamp = {timestamp}\nseed = {seed}\n\n"
+        )
+        .context("writing seed history")?;
+    }
+
     #[culpa::throws]
     #[tracing::instrument(skip_all, fields(world.directory = %self.directory, uuid = %uuid))]
     pub(crate) fn player(&self, uuid: Uuid) -> Player {
@@ -80,4 +120,62 @@ impl World {
                     .transpose()
             })
     }
+
+    #[culpa::throws]
+    #[tracing::instrument(skip_all, fields(world.directory = %self.directory, map.id = id))]
+    pub(crate) fn map(&self, id: u32) -> Map {
+        Map {
+            id,
+            data: read_compound(&self.directory.join("data").join(format!("map_{id}.dat")))?,
+        }
+    }
+
+    #[culpa::throws]
+    #[tracing::instrument(skip_all, fields(world.directory = %self.directory, map.id = map.id))]
+    pub(crate) fn save_map(&self, map: &Map) {
+        let Map { id, data } = map;
+        write_compound(
+            &self.directory.join("data").join(format!("map_{id}.dat")),
+            data,
+        )?;
+    }
+
+    /// Keeps a copy of the map's current data in the archive directory, with a timestamp so that
+    /// archives from multiple resets don't collide
+    #[culpa::throws]
+    #[tracing::instrument(skip_all, fields(world.directory = %self.directory, map.id = id))]
+    pub(crate) fn archive_map(&self, id: u32, timestamp: u64) {
+        let archive = self.directory.join("data").join("fc5-tool-archive");
+        std::fs::create_dir_all(&archive).context("creating map archive dir")?;
+        std::fs::copy(
+            self.directory.join("data").join(format!("map_{id}.dat")),
+            archive.join(format!("map_{id}-{timestamp}.dat")),
+        )
+        .context("archiving map")?;
+    }
+
+    #[culpa::throws]
+    #[tracing::instrument(skip_all, fields(world.directory = %self.directory))]
+    pub(crate) fn maps(&self) -> impl Iterator<Item = Result<u32>> {
+        std::fs::read_dir(self.directory.join("data"))
+            .context("reading data dir")?
+            .filter_map(|entry| {
+                entry
+                    .context("reading dir entry")
+                    .and_then(|entry| {
+                        let filename = entry.file_name();
+                        let filename = filename.to_str().context("non utf-8 filename")?;
+                        let _guard =
+                            tracing::info_span!("parsing filename", filename = %filename).entered();
+                        let Some(id) = filename
+                            .strip_prefix("map_")
+                            .and_then(|filename| filename.strip_suffix(".dat"))
+                        else {
+                            return Ok(None);
+                        };
+                        Ok(Some(id.parse()?))
+                    })
+                    .transpose()
+            })
+    }
 }
@@ -1,51 +1,121 @@
 #![allow(clippy::needless_question_mark)] // stupid lint, TODO: remove on 1.74
 
+use camino::Utf8PathBuf;
 use clap::Parser;
-use eyre::Error;
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use eyre::{Context, Error};
+use std::sync::Mutex;
+use tracing_subscriber::{
+    fmt::writer::BoxMakeWriter, layer::SubscriberExt, util::SubscriberInitExt, Layer, Registry,
+};
 
 mod app;
 mod config;
 mod data;
+mod metrics;
+mod progress;
+mod report;
 
 #[derive(Debug, clap::Parser)]
+#[command(subcommand_negates_reqs = true, args_conflicts_with_subcommands = true)]
 struct Args {
-    #[arg(long, short, action = clap::ArgAction::Count)]
+    #[arg(long, short, global = true, action = clap::ArgAction::Count)]
     verbose: u8,
 
-    #[arg(long, short, action = clap::ArgAction::Count)]
+    #[arg(long, short, global = true, action = clap::ArgAction::Count)]
     quiet: u8,
 
-    #[arg(long)]
+    #[arg(long, global = true)]
     trace: bool,
 
+    /// How to format log lines, json keeps span fields as structured keys for log aggregators
+    #[arg(long, global = true, value_enum, default_value_t)]
+    log_format: LogFormat,
+
+    /// Append logs to this file instead of writing them to stderr
+    #[arg(long, global = true, value_name = "PATH")]
+    log_file: Option<Utf8PathBuf>,
+
     #[command(flatten)]
     app: app::App,
 }
 
+#[derive(Clone, Copy, Debug, Default, clap::ValueEnum)]
+enum LogFormat {
+    /// Indented by span, for reading interactively
+    #[default]
+    Tree,
+    /// One JSON object per line, with the event and span fields as keys
+    Json,
+    /// One line per event, prefixed by its spans
+    Compact,
+}
+
 #[culpa::throws]
 fn main() {
     color_eyre::install()?;
 
     let args = Args::parse();
 
+    // hidden when stderr isn't a terminal, stages then log their progress instead
+    let bars = indicatif::MultiProgress::new();
+    let warnings = report::Warnings::default();
+
+    let (writer, ansi) = match &args.log_file {
+        Some(path) => {
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .with_context(|| format!("opening log file {path}"))?;
+            (BoxMakeWriter::new(Mutex::new(file)), false)
+        }
+        None => (BoxMakeWriter::new(progress::LogWriter(bars.clone())), true),
+    };
+
+    let logs: Box<dyn Layer<Registry> + Send + Sync> = match args.log_format {
+        LogFormat::Tree => tracing_tree::HierarchicalLayer::new(2)
+            .with_writer(writer)
+            .with_ansi(ansi)
+            .with_targets(true)
+            .with_print_span_elapsed(false)
+            .with_delay_spans(!args.trace)
+            .boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .json()
+            .flatten_event(true)
+            .with_current_span(true)
+            .with_span_list(true)
+            .with_writer(writer)
+            .boxed(),
+        LogFormat::Compact => tracing_subscriber::fmt::layer()
+            .compact()
+            .with_ansi(ansi)
+            .with_writer(writer)
+            .boxed(),
+    };
+
+    let level = match i16::from(args.verbose) - i16::from(args.quiet) {
+        i16::MIN..=-3 => tracing_subscriber::filter::LevelFilter::ERROR,
+        -2 => tracing_subscriber::filter::LevelFilter::ERROR,
+        -1 => tracing_subscriber::filter::LevelFilter::WARN,
+        0 => tracing_subscriber::filter::LevelFilter::INFO,
+        1 => tracing_subscriber::filter::LevelFilter::DEBUG,
+        2..=i16::MAX => tracing_subscriber::filter::LevelFilter::TRACE,
+    };
+
+    // verbosity only applies to the logs, the report collects warnings however quiet the logs are
     tracing_subscriber::registry()
+        .with(logs.with_filter(level))
+        .with(tracing_error::ErrorLayer::default())
         .with(
-            tracing_tree::HierarchicalLayer::new(2)
-                .with_targets(true)
-                .with_print_span_elapsed(false)
-                .with_delay_spans(!args.trace),
+            warnings
+                .clone()
+                .with_filter(tracing_subscriber::filter::filter_fn(|metadata| {
+                    // spans give the warnings logged in them context
+                    metadata.is_span() || *metadata.level() <= tracing::Level::WARN
+                })),
         )
-        .with(tracing_error::ErrorLayer::default())
-        .with(match i16::from(args.verbose) - i16::from(args.quiet) {
-            i16::MIN..=-3 => tracing_subscriber::filter::LevelFilter::ERROR,
-            -2 => tracing_subscriber::filter::LevelFilter::ERROR,
-            -1 => tracing_subscriber::filter::LevelFilter::WARN,
-            0 => tracing_subscriber::filter::LevelFilter::INFO,
-            1 => tracing_subscriber::filter::LevelFilter::DEBUG,
-            2..=i16::MAX => tracing_subscriber::filter::LevelFilter::TRACE,
-        })
         .init();
 
-    args.app.run()?;
+    args.app.run(&bars, &warnings)?;
 }
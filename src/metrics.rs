@@ -0,0 +1,168 @@
+use camino::{Utf8Path, Utf8PathBuf};
+use eyre::{Context, Error};
+use std::{fmt::Display, time::SystemTime};
+
+use crate::{config::Config, data::dimension, report::Report};
+
+/// Metrics in the Prometheus text format, every one a gauge of the latest run
+#[derive(Debug, Default)]
+struct Metrics(String);
+
+impl Metrics {
+    fn header(&mut self, name: &str, help: &str) {
+        self.0.push_str(&format!(
+            "# HELP fc5_tool_{name} {help}\n# TYPE fc5_tool_{name} gauge\n"
+        ));
+    }
+
+    fn gauge(&mut self, name: &str, help: &str, value: impl Display) {
+        self.header(name, help);
+        self.0.push_str(&format!("fc5_tool_{name} {value}\n"));
+    }
+
+    fn dimension_gauge<V: Display>(
+        &mut self,
+        name: &str,
+        help: &str,
+        values: impl IntoIterator<Item = (dimension::Kind, V)>,
+    ) {
+        self.header(name, help);
+        for (dimension, value) in values {
+            self.0.push_str(&format!(
+                "fc5_tool_{name}{{dimension=\"{dimension}\"}} {value}\n"
+            ));
+        }
+    }
+}
+
+/// Writes metrics about a successful run for node_exporter's textfile collector
+///
+/// The file is written beside the target and renamed over it, so the collector never sees a
+/// partial file.
+#[culpa::throws]
+#[tracing::instrument(skip(report, config))]
+pub(crate) fn write(path: &Utf8Path, report: &Report, config: &Config) {
+    let mut metrics = Metrics::default();
+
+    let dimensions = &report.dimensions;
+    metrics.dimension_gauge(
+        "deleted_regions",
+        "Region files deleted for being entirely outside persistent areas",
+        dimensions
+            .iter()
+            .map(|(k, d)| (*k, d.deleted_regions.count)),
+    );
+    metrics.dimension_gauge(
+        "deleted_chunks",
+        "Chunks deleted from regions that were kept",
+        dimensions.iter().map(|(k, d)| (*k, d.deleted_chunks.count)),
+    );
+    metrics.dimension_gauge(
+        "deleted_entity_regions",
+        "Entity region files deleted for being entirely outside persistent areas",
+        dimensions
+            .iter()
+            .map(|(k, d)| (*k, d.deleted_entity_regions.count)),
+    );
+    metrics.dimension_gauge(
+        "deleted_entity_chunks",
+        "Entity chunks deleted from entity regions that were kept",
+        dimensions
+            .iter()
+            .map(|(k, d)| (*k, d.deleted_entity_chunks.count)),
+    );
+    metrics.dimension_gauge(
+        "kept_regions",
+        "Region files kept for overlapping persistent areas",
+        dimensions.iter().map(|(k, d)| (*k, d.kept_regions)),
+    );
+    metrics.dimension_gauge(
+        "kept_chunks",
+        "Chunks kept for being inside persistent areas",
+        dimensions.iter().map(|(k, d)| (*k, d.kept_chunks)),
+    );
+    metrics.dimension_gauge(
+        "blended_chunks",
+        "Chunks marked for blending on the border of persistent areas",
+        dimensions.iter().map(|(k, d)| (*k, d.blended_chunks.len())),
+    );
+    metrics.dimension_gauge(
+        "reclaimed_bytes",
+        "Bytes freed by deleting regions and compacting the ones kept",
+        dimensions.iter().map(|(k, d)| (*k, d.reclaimed_bytes)),
+    );
+    metrics.dimension_gauge(
+        "persistent_areas",
+        "Configured persistent areas, including ones added around out of bounds players",
+        config
+            .dimension
+            .iter()
+            .map(|(k, d)| (*k, d.persistent.len())),
+    );
+    metrics.dimension_gauge(
+        "persistent_chunks",
+        "Chunks inside persistent areas",
+        config
+            .dimension
+            .iter()
+            .map(|(k, d)| (*k, d.kept_chunks().len())),
+    );
+    metrics.gauge(
+        "added_persistent_areas",
+        "Persistent areas added around out of bounds players",
+        report.added_areas.len(),
+    );
+    metrics.gauge(
+        "relocated_players",
+        "Players moved out of chunks that were deleted",
+        report.relocated_players.len(),
+    );
+    metrics.gauge(
+        "run_duration_seconds",
+        "How long the run took",
+        report.elapsed().as_secs_f64(),
+    );
+    metrics.gauge(
+        "last_success_timestamp_seconds",
+        "Unix timestamp the last successful run finished at",
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)?
+            .as_secs(),
+    );
+
+    let partial = Utf8PathBuf::from(format!("{path}.tmp"));
+    std::fs::write(&partial, metrics.0).context("writing metrics")?;
+    std::fs::rename(&partial, path).context("replacing metrics")?;
+    tracing::info!("Wrote metrics");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Metrics;
+    use crate::data::dimension;
+    use indoc::indoc;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn dimension_gauge_is_labelled() {
+        let mut metrics = Metrics::default();
+        metrics.dimension_gauge(
+            "kept_chunks",
+            "Chunks kept",
+            [(dimension::Kind::Overworld, 4), (dimension::Kind::End, 0)],
+        );
+        metrics.gauge("relocated_players", "Players moved", 1);
+        assert_eq!(
+            metrics.0,
+            indoc! {r#"
+                # HELP fc5_tool_kept_chunks Chunks kept
+                # TYPE fc5_tool_kept_chunks gauge
+                fc5_tool_kept_chunks{dimension="overworld"} 4
+                fc5_tool_kept_chunks{dimension="end"} 0
+                # HELP fc5_tool_relocated_players Players moved
+                # TYPE fc5_tool_relocated_players gauge
+                fc5_tool_relocated_players 1
+            "#}
+        );
+    }
+}
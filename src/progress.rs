@@ -0,0 +1,107 @@
+use indicatif::{HumanDuration, MultiProgress, ProgressBar, ProgressStyle};
+use std::{
+    io::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::data::dimension;
+
+/// How often progress is logged when stderr isn't a terminal to draw bars on
+const LOG_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Writes log lines above the progress bars, rather than garbling them
+#[derive(Clone, Debug)]
+pub(crate) struct LogWriter(pub(crate) MultiProgress);
+
+impl Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.suspend(|| std::io::stderr().write(buf))
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        std::io::stderr().flush()
+    }
+}
+
+impl tracing_subscriber::fmt::MakeWriter<'_> for LogWriter {
+    type Writer = Self;
+
+    fn make_writer(&self) -> Self::Writer {
+        self.clone()
+    }
+}
+
+/// Live progress through the regions of one stage in one dimension, can be updated from any thread
+///
+/// When stderr isn't a terminal the bar is hidden, and progress is logged every
+/// [`LOG_INTERVAL`] instead.
+#[derive(Debug)]
+pub(crate) struct Progress {
+    bar: ProgressBar,
+    chunks: AtomicU64,
+    /// When progress was last logged, only used while the bar is hidden
+    last_logged: Mutex<Instant>,
+}
+
+impl Progress {
+    pub(crate) fn new(
+        bars: &MultiProgress,
+        stage: &str,
+        dimension: dimension::Kind,
+        regions: usize,
+    ) -> Self {
+        let bar = bars.add(
+            ProgressBar::new(u64::try_from(regions).unwrap_or(u64::MAX))
+                .with_style(
+                    ProgressStyle::with_template(
+                        "{prefix} [{bar:40}] {pos}/{len} regions, {msg} chunks, ETA {eta}",
+                    )
+                    .expect("progress template is valid")
+                    .progress_chars("=> "),
+                )
+                .with_prefix(format!("{stage} {dimension}"))
+                .with_message("0"),
+        );
+        Self {
+            bar,
+            chunks: AtomicU64::new(0),
+            last_logged: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Records that a region is done, along with how many chunks in it were processed
+    pub(crate) fn region_done(&self, chunks: usize) {
+        let chunks = u64::try_from(chunks).unwrap_or(u64::MAX);
+        let chunks = self.chunks.fetch_add(chunks, Ordering::Relaxed) + chunks;
+        self.bar.set_message(chunks.to_string());
+        self.bar.inc(1);
+
+        if self.bar.is_hidden() {
+            let mut last_logged = self
+                .last_logged
+                .lock()
+                .unwrap_or_else(std::sync::PoisonError::into_inner);
+            if last_logged.elapsed() >= LOG_INTERVAL {
+                *last_logged = Instant::now();
+                tracing::info!(
+                    "{}: {}/{} regions, {chunks} chunks, ETA {}",
+                    self.bar.prefix(),
+                    self.bar.position(),
+                    self.bar.length().unwrap_or_default(),
+                    HumanDuration(self.bar.eta()),
+                );
+            }
+        }
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        // the stage logs its own summary once done
+        self.bar.finish_and_clear();
+    }
+}
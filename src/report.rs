@@ -0,0 +1,276 @@
+use camino::Utf8Path;
+use eyre::{Context, Error};
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
+use tracing_subscriber::{layer::Context as LayerContext, registry::LookupSpan, Layer};
+
+use crate::data::{dimension, Coord, Coord3, Direction};
+
+/// A list of coordinates along with how many there are, so the count doesn't have to be derived
+#[derive(Debug, Default, serde::Serialize)]
+pub(crate) struct Coords {
+    pub(crate) count: usize,
+    pub(crate) coords: Vec<Coord<i64>>,
+}
+
+impl Coords {
+    pub(crate) fn push(&mut self, coord: Coord<i64>) {
+        self.count += 1;
+        self.coords.push(coord);
+    }
+}
+
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct BlendedChunk {
+    pub(crate) coord: Coord<i64>,
+    pub(crate) directions: [Direction; 2],
+    /// The offset applied to the height data, `None` when minecraft creates it
+    pub(crate) height_offset: Option<f64>,
+}
+
+#[derive(Debug, Default, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct DimensionReport {
+    pub(crate) deleted_regions: Coords,
+    pub(crate) deleted_chunks: Coords,
+    pub(crate) deleted_entity_regions: Coords,
+    pub(crate) deleted_entity_chunks: Coords,
+    pub(crate) kept_regions: usize,
+    pub(crate) kept_chunks: usize,
+    pub(crate) blended_chunks: Vec<BlendedChunk>,
+    /// Bytes freed by deleting regions and compacting the ones kept
+    pub(crate) reclaimed_bytes: u64,
+}
+
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct RelocatedPlayer {
+    pub(crate) uuid: String,
+    pub(crate) old_dimension: dimension::Kind,
+    pub(crate) old_position: Coord3,
+    pub(crate) new_dimension: dimension::Kind,
+    pub(crate) new_position: Coord3,
+}
+
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct AddedArea {
+    pub(crate) player: String,
+    pub(crate) dimension: dimension::Kind,
+    pub(crate) top_left: Coord<i64>,
+    pub(crate) bottom_right: Coord<i64>,
+}
+
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct SeedChange {
+    pub(crate) old: Option<i64>,
+    pub(crate) new: i64,
+    pub(crate) cycle: u64,
+}
+
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct Timing {
+    pub(crate) stage: &'static str,
+    pub(crate) seconds: f64,
+}
+
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum Status {
+    Succeeded,
+    Failed,
+}
+
+/// A structured summary of a run, for `--report`
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct Report {
+    /// Unix timestamp the run started at
+    pub(crate) started: u64,
+    pub(crate) seconds: f64,
+    pub(crate) status: Status,
+    /// Why the run failed, stages after the failing one didn't run
+    pub(crate) error: Option<String>,
+    pub(crate) dimensions: BTreeMap<dimension::Kind, DimensionReport>,
+    pub(crate) relocated_players: Vec<RelocatedPlayer>,
+    pub(crate) added_areas: Vec<AddedArea>,
+    pub(crate) seed: Option<SeedChange>,
+    pub(crate) warnings: Vec<String>,
+    /// Regions and chunks skipped because of `--on-error`
+    pub(crate) skipped: Vec<String>,
+    pub(crate) timings: Vec<Timing>,
+    #[serde(skip)]
+    start: Instant,
+}
+
+impl Report {
+    #[culpa::throws]
+    pub(crate) fn new() -> Self {
+        Self {
+            started: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)?
+                .as_secs(),
+            seconds: 0.0,
+            status: Status::Succeeded,
+            error: None,
+            dimensions: BTreeMap::new(),
+            relocated_players: Vec::new(),
+            added_areas: Vec::new(),
+            seed: None,
+            warnings: Vec::new(),
+            skipped: Vec::new(),
+            timings: Vec::new(),
+            start: Instant::now(),
+        }
+    }
+
+    pub(crate) fn dimension(&mut self, kind: dimension::Kind) -> &mut DimensionReport {
+        self.dimensions.entry(kind).or_default()
+    }
+
+    /// Records how long a stage took, given when it started
+    pub(crate) fn stage_done(&mut self, stage: &'static str, started: Instant) {
+        self.timings.push(Timing {
+            stage,
+            seconds: started.elapsed().as_secs_f64(),
+        });
+    }
+
+    pub(crate) fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    /// Writes the report, along with the error that ended the run if it failed
+    #[culpa::throws]
+    #[tracing::instrument(skip(self, warnings, error))]
+    pub(crate) fn save(&mut self, path: &Utf8Path, warnings: &Warnings, error: Option<&Error>) {
+        self.seconds = self.elapsed().as_secs_f64();
+        self.warnings = warnings.take();
+        if let Some(error) = error {
+            self.status = Status::Failed;
+            self.error = Some(format!("{error:#}"));
+        }
+        let file = std::fs::File::create(path).context("creating report")?;
+        serde_json::to_writer_pretty(std::io::BufWriter::new(file), self)
+            .context("writing report")?;
+        tracing::info!("Wrote report");
+    }
+}
+
+/// Collects every warning and error logged, along with the spans they were logged in
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Warnings(Arc<Mutex<Vec<String>>>);
+
+impl Warnings {
+    fn take(&self) -> Vec<String> {
+        std::mem::take(
+            &mut self
+                .0
+                .lock()
+                .unwrap_or_else(std::sync::PoisonError::into_inner),
+        )
+    }
+}
+
+/// The fields of a span or event formatted as `key=value`, with the message on its own
+#[derive(Debug, Default)]
+struct Fields {
+    message: String,
+    fields: String,
+}
+
+impl tracing::field::Visit for Fields {
+    fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            self.message.push_str(&format!("{value:?}"));
+        } else {
+            let separator = if self.fields.is_empty() { "" } else { " " };
+            self.fields
+                .push_str(&format!("{separator}{}={value:?}", field.name()));
+        }
+    }
+
+    fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+        if field.name() == "message" {
+            self.message.push_str(value);
+        } else {
+            self.record_debug(field, &value);
+        }
+    }
+}
+
+impl<S> Layer<S> for Warnings
+where
+    S: tracing::Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(
+        &self,
+        attrs: &tracing::span::Attributes<'_>,
+        id: &tracing::span::Id,
+        ctx: LayerContext<'_, S>,
+    ) {
+        let mut fields = Fields::default();
+        attrs.record(&mut fields);
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(fields);
+        }
+    }
+
+    fn on_event(&self, event: &tracing::Event<'_>, ctx: LayerContext<'_, S>) {
+        if *event.metadata().level() > tracing::Level::WARN {
+            return;
+        }
+        let mut warning = String::new();
+        for span in ctx
+            .event_scope(event)
+            .into_iter()
+            .flat_map(|scope| scope.from_root())
+        {
+            warning.push_str(span.name());
+            if let Some(fields) = span.extensions().get::<Fields>() {
+                if !fields.fields.is_empty() {
+                    warning.push_str(&format!("{{{}}}", fields.fields));
+                }
+            }
+            warning.push_str(": ");
+        }
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        warning.push_str(&fields.message);
+        if !fields.fields.is_empty() {
+            warning.push_str(&format!(" {{{}}}", fields.fields));
+        }
+        self.0
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .push(warning);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Warnings;
+    use pretty_assertions::assert_eq;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[test]
+    fn warnings_keep_span_context() {
+        let warnings = Warnings::default();
+        let subscriber = tracing_subscriber::registry().with(warnings.clone());
+        tracing::subscriber::with_default(subscriber, || {
+            let _guard = tracing::info_span!("dimension", dimension.kind = "overworld").entered();
+            tracing::info!("Not collected");
+            tracing::warn!(count = 2, "Missing chunks");
+        });
+        assert_eq!(
+            warnings.take(),
+            ["dimension{dimension.kind=\"overworld\"}: Missing chunks {count=2}"]
+        );
+    }
+}